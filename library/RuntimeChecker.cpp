@@ -1,5 +1,9 @@
 #include "llvm/Transforms/Utils/RuntimeChecker.h"
+#include "llvm/IR/DebugInfoMetadata.h"
 #include "llvm/IR/Instructions.h"
+#include "llvm/Support/Path.h"
+#include "llvm/Support/Process.h"
+#include <cstdlib>
 #include <sstream>
 #include <iostream>
 
@@ -24,6 +28,22 @@ unsigned succNumOf(BasicBlock *BB) {
     return std::distance(succ_begin(BB), succ_end(BB));
 }
 
+std::string printIR(const Value *V) {
+    std::string IR;
+    raw_string_ostream OS(IR);
+    V->print(OS);
+    return StringRef(OS.str()).trim().str();
+}
+
+std::string printLoc(const DebugLoc &DL) {
+    if (!DL)
+        return "";
+    std::string Loc;
+    raw_string_ostream OS(Loc);
+    OS << DL->getFilename() << ":" << DL.getLine() << ":" << DL.getCol();
+    return OS.str();
+}
+
 StringRef UKindToString(UpdateKind K) {
     switch (K) {
         case UpdateKind::Preserving:
@@ -109,25 +129,63 @@ UpdateKind DebugLocDstM::properUpdateKind() {
     assert(false && "No match of proper update kind!");
 }
 
+json::Value IRContext::toJSON() const {
+    json::Object Context{
+        {"name", Name},
+        {"ir", IR},
+        {"loc", Loc.empty() ? json::Value(nullptr) : json::Value(Loc)},
+    };
+    if (Site)
+        Context["site"] = Site;
+    return Context;
+}
+
+void DebugLocDstM::snapshot(StringRef Name) {
+    // getFunction() dereferences the parent, which a removed or not yet
+    // inserted instruction lacks
+    Function *F = TheInst->getParent() ? TheInst->getFunction() : nullptr;
+    if (F)
+        DstFunction = F->getName().str();
+
+    if (!DstContext) {
+        DstContext = IRContext{0, Name.str(), "", printLoc(TheInst->getDebugLoc())};
+    } else if (DstContext->Name.empty()) {
+        DstContext->Name = Name.str();
+    }
+    DstContext->IR = printIR(TheInst);
+}
+
+void DebugLocDstM::addSource(unsigned Site, StringRef Name, Instruction *Src) {
+    SrcContexts.push_back(IRContext{Site, Name.str(), printIR(Src), printLoc(Src->getDebugLoc())});
+}
+
+StringRef DebugLocDstM::verdict(UpdateKind ProperKind) const {
+    if (InCodeUpdateKind != UpdateKind::None && InCodeUpdateKind == ProperKind)
+        return "pass";
+    if (ProperKind == UpdateKind::Others)
+        return "warn";
+    return "fail";
+}
+
+SmallVector<unsigned, 2> DebugLocDstM::sortedReplaceSites() const {
+    SmallVector<unsigned, 2> Sites(ReplaceSite.begin(), ReplaceSite.end());
+    llvm::sort(Sites);
+    return Sites;
+}
+
 std::string DebugLocDstM::toString() {
     std::stringstream ss;
     UpdateKind ProperKind = properUpdateKind();
-    if (InCodeUpdateKind != UpdateKind::None && InCodeUpdateKind == ProperKind) {
-        ss << "pass: ";
-    } else {
-        if (ProperKind == UpdateKind::Others)
-            ss << "warn: ";
-        else
-            ss << "fail: ";
-    }
+    ss << verdict(ProperKind).str() << ": ";
 
     ss << UKindToString(ProperKind).str();
     ss << " [Construct: " << ConstructSite << ", " << CKindToString(CKind).str();
     if (!ReplaceSite.empty()) {
         ss << "; Replace: ";
-        for (auto iter = ReplaceSite.begin(); iter != ReplaceSite.end(); ) {
+        auto Sites = sortedReplaceSites();
+        for (auto iter = Sites.begin(); iter != Sites.end(); ) {
             ss << *iter;
-            if (++iter != ReplaceSite.end())
+            if (++iter != Sites.end())
                 ss << ", ";
         }
     }
@@ -141,6 +199,52 @@ std::string DebugLocDstM::toString() {
     return ss.str();
 }
 
+json::Value DebugLocDstM::toJSON(StringRef ModuleName, StringRef FunctionName) {
+    UpdateKind ProperKind = properUpdateKind();
+
+    json::Array ReplaceSites;
+    for (unsigned Site : sortedReplaceSites())
+        ReplaceSites.push_back(Site);
+
+    json::Value Update = nullptr;
+    if (InCodeUpdateKind != UpdateKind::None)
+        Update = json::Object{
+            {"kind", UKindToString(InCodeUpdateKind).str()},
+            {"site", InCodeUpdateSite},
+        };
+
+    json::Value Insert = nullptr;
+    if (InsertSite)
+        Insert = InsertSite;
+
+    json::Array Sources;
+    for (const IRContext &Src : SrcContexts)
+        Sources.push_back(Src.toJSON());
+
+    json::Object Context{
+        {"function", DstFunction.empty() ? FunctionName.str() : DstFunction},
+        {"dst", DstContext ? DstContext->toJSON() : json::Value(nullptr)},
+        {"srcs", std::move(Sources)},
+    };
+
+    return json::Object{
+        {"schema", DISAN_LOG_SCHEMA},
+        {"verdict", verdict(ProperKind).str()},
+        {"pass", VarName},
+        {"module", ModuleName.str()},
+        {"function", FunctionName.str()},
+        {"construct", json::Object{
+            {"kind", CKindToString(CKind).str()},
+            {"site", ConstructSite},
+        }},
+        {"replace_sites", std::move(ReplaceSites)},
+        {"insert_site", std::move(Insert)},
+        {"expected", UKindToString(ProperKind).str()},
+        {"update", std::move(Update)},
+        {"context", std::move(Context)},
+    };
+}
+
 //===----------------------------------------------------------------------===//
 //                          Simple fact queries
 //===----------------------------------------------------------------------===//
@@ -161,29 +265,37 @@ bool RuntimeChecker::inDominantRegionOf(Instruction *Dst, Instruction *Src) {
         || PDT->dominates(Src, Dst);            // Post-dominated by Src
 }
 
+bool RuntimeChecker::inDominantRegionOf(BasicBlock *DstBB, Instruction *Src) {
+    assert(DstBB && Src);
+    assert(DstBB->getParent() == Src->getFunction());
+
+    DT->recalculate(*DstBB->getParent());
+    PDT->recalculate(*DstBB->getParent());
+
+    // An instruction in another block (post-)dominates every position in DstBB
+    // iff its block does
+    BasicBlock *SrcBB = Src->getParent();
+    return DstBB == SrcBB
+        || DT->dominates(SrcBB, DstBB)
+        || PDT->dominates(SrcBB, DstBB);
+}
+
 //===----------------------------------------------------------------------===//
 //              Track all debug location destinations in the pass
 //===----------------------------------------------------------------------===//
 
 void RuntimeChecker::trackDebugLocDstImpl(        
     Instruction *DebugLocDstInst,
-    Value *ExtraValue, /* B, A = Create(..., B) or A->moveBefore(B) or A = B->clone() */
+    Value *ExtraValue, /* B, A = Create(..., B) or A->moveBefore(B) or A = B->clone(),
+                          or the block of A->moveBefore(B, It) */
     ConstructKind Kind,
-    unsigned SrcLine
+    unsigned SrcLine,
+    StringRef DLDName,
+    StringRef ExtraName
 ) {
     assert(DebugLocDstInst);
     InstToDLDMap[DebugLocDstInst] = new DebugLocDstM(PassName.str(), SrcLine, Kind, DebugLocDstInst);
-
-    Instruction *DummyInst = nullptr;
-    if (ExtraValue) {
-        if (BasicBlock *BB = dyn_cast<BasicBlock>(ExtraValue)) {
-            DummyInst = PHINode::Create(DebugLocDstInst->getType(), 0, "", BB);
-#ifdef DEBUG
-            dbgs() << "Create dummy: " << *DummyInst << "\n";
-#endif
-            ExtraValue = DummyInst;
-        }
-    }
+    InstToDLDMap[DebugLocDstInst]->snapshot(DLDName);
 
     switch (Kind) {
         case ConstructKind::Creating: break;
@@ -192,26 +304,25 @@ void RuntimeChecker::trackDebugLocDstImpl(
             Instruction *OriginalInst = dyn_cast<Instruction>(ExtraValue);
             assert(OriginalInst && "The cloned instruction is not an instruction!");
             InstToDLDMap[DebugLocDstInst]->setOriginalInst(OriginalInst);
+            InstToDLDMap[DebugLocDstInst]->addSource(SrcLine, ExtraName, OriginalInst);
             break;
         }
         case ConstructKind::Moving: {
             assert(ExtraValue && "The destination of the move is not given!");
-            Instruction *MovePosInst = dyn_cast<Instruction>(ExtraValue);
-            assert(MovePosInst && "The destination of the move is not an instruction!");
-            bool IsDominated = inDominantRegionOf(MovePosInst, DebugLocDstInst);
+            bool IsDominated;
+            if (BasicBlock *MoveBB = dyn_cast<BasicBlock>(ExtraValue)) {
+                IsDominated = inDominantRegionOf(MoveBB, DebugLocDstInst);
+            } else {
+                Instruction *MovePosInst = dyn_cast<Instruction>(ExtraValue);
+                assert(MovePosInst && "The destination of the move is not an instruction!");
+                IsDominated = inDominantRegionOf(MovePosInst, DebugLocDstInst);
+            }
             InstToDLDMap[DebugLocDstInst]->moveAt(SrcLine, IsDominated);
             break;
         }
         default:
             assert(false && "No such construct kind!");
     }
-
-    if (DummyInst) {
-#ifdef DEBUG
-        dbgs() << "Destory dummy: " << *DummyInst << '\n';
-#endif
-        DummyInst->removeFromParent();
-    }
 }
 
 void RuntimeChecker::trackDebugLocDst(
@@ -222,7 +333,8 @@ void RuntimeChecker::trackDebugLocDst(
         std::string DLDName,
         std::string IPName
 ) {
-    if (dyn_cast<BasicBlock>(DebugLocDst)) return ;
+    // A clone failing its dyn_cast, e.g., `dyn_cast<PHINode>(I->clone())`, is null
+    if (!DebugLocDst || dyn_cast<BasicBlock>(DebugLocDst)) return ;
 #ifdef DEBUG
     dbgs() << "[TrackDebugLocDst] \033[31;1m" << SrcLine << ":\033[0m " << *DebugLocDst << "\n";
 #endif
@@ -231,25 +343,38 @@ void RuntimeChecker::trackDebugLocDst(
 
     assert(DebugLocDstInst && "Creating a non instruction value!");
 
-    trackDebugLocDstImpl(DebugLocDstInst, ExtraValue, Kind, SrcLine);
+    trackDebugLocDstImpl(DebugLocDstInst, ExtraValue, Kind, SrcLine, DLDName, IPName);
 }
 
-// void RuntimeChecker::trackDebugLocDst(
-//     Value *DebugLocDst,
-//     BasicBlock::iterator ExtraIter,
-//     ConstructKind Kind,
-//     unsigned SrcLine,
-//     std::string DLDName,
-//     std::string IPName
-// ) {
-//     dbgs() << "[TrackDebugLocDst] \033[31;1m" << SrcLine << ":\033[0m " << *DebugLocDst << "\n";
-//     if (dyn_cast<BasicBlock>(DebugLocDst)) return ;
-
-//     Instruction *DebugLocDstInst = dyn_cast<Instruction>(DebugLocDst);
-//     assert(DebugLocDstInst && "Creating a non instruction value!");
+void RuntimeChecker::trackDebugLocDst(
+        Instruction *DebugLocDst,
+        BasicBlock &MoveBB,
+        BasicBlock::iterator MovePos,
+        ConstructKind Kind,
+        unsigned SrcLine,
+        std::string DLDName,
+        std::string IPName
+) {
+#ifdef DEBUG
+    dbgs() << "[TrackDebugLocDst] \033[31;1m" << SrcLine << ":\033[0m " << *DebugLocDst << "\n";
+#endif
+    // The end() of a block has no instruction to dereference, so the block stands for it
+    Value *MovePosValue = MovePos == MoveBB.end() ? static_cast<Value *>(&MoveBB) : &*MovePos;
+    trackDebugLocDstImpl(DebugLocDst, MovePosValue, Kind, SrcLine, DLDName, IPName);
+}
 
-//     trackDebugLocDstImpl(DebugLocDstInst, &*ExtraIter, Kind, SrcLine);
-// }
+void RuntimeChecker::trackDebugLocDst(
+        Instruction *DebugLocDst,
+        BasicBlock::iterator MovePos,
+        ConstructKind Kind,
+        unsigned SrcLine,
+        std::string DLDName,
+        std::string IPName
+) {
+    // Even the end() knows its block, which Instruction::moveBefore(InstListType::iterator)
+    // relies on as well
+    trackDebugLocDst(DebugLocDst, *MovePos->getParent(), MovePos, Kind, SrcLine, DLDName, IPName);
+}
 
 //===----------------------------------------------------------------------===//
 //              Track all debug location sources in the pass
@@ -277,12 +402,11 @@ void RuntimeChecker::trackDebugLocSrc(
         << *DebugLocSrcInst << " (" << DebugLocSrcInst->getParent()->getName() << ")\n";
 #endif
 
-    if (InstToDLDMap.contains(DebugLocDstInst)) {
-        InstToDLDMap[DebugLocDstInst]->replaceAt(SrcLine, IsDominated);
-    } else {
+    if (!InstToDLDMap.contains(DebugLocDstInst))
         InstToDLDMap[DebugLocDstInst] = new DebugLocDstM(PassName.str(), SrcLine, ConstructKind::Untracked, DebugLocDstInst);
-        InstToDLDMap[DebugLocDstInst]->replaceAt(SrcLine, IsDominated);
-    }
+    InstToDLDMap[DebugLocDstInst]->replaceAt(SrcLine, IsDominated);
+    InstToDLDMap[DebugLocDstInst]->snapshot(DLDName);
+    InstToDLDMap[DebugLocDstInst]->addSource(SrcLine, DLSName, DebugLocSrcInst);
 }
 
 //===----------------------------------------------------------------------===//
@@ -298,6 +422,7 @@ void RuntimeChecker::trackDebugLocPreserving(
 ) {
     if (InstToDLDMap.contains(DebugLocDst)) {
         InstToDLDMap[DebugLocDst]->updateAt(SrcLine, UpdateKind::Preserving);
+        InstToDLDMap[DebugLocDst]->snapshot(DLDName);
     } else {
 #ifdef DEBUG
         dbgs() << YELLOW << "[TrackPres] Preserving debugloc of an untracked instruction at " << SrcLine << RESET << "\n";
@@ -317,6 +442,7 @@ void RuntimeChecker::trackDebugLocMerging(
 ) {
     if (InstToDLDMap.contains(DebugLocDst)) {
         InstToDLDMap[DebugLocDst]->updateAt(SrcLine, UpdateKind::Merging);
+        InstToDLDMap[DebugLocDst]->snapshot(DLDName);
     } else {
 #ifdef DEBUG
         dbgs() << YELLOW << "[TrackPres] Merging debugloc of an untracked instruction at " << SrcLine << RESET << "\n";
@@ -332,6 +458,7 @@ void RuntimeChecker::trackDebugLocDropping(
 ) {
     if (InstToDLDMap.contains(DebugLocDst)) {
         InstToDLDMap[DebugLocDst]->updateAt(SrcLine, UpdateKind::Dropping);
+        InstToDLDMap[DebugLocDst]->snapshot(DLDName);
     } else {
 #ifdef DEBUG
         dbgs() << YELLOW << "[TrackPres] Dropping debugloc of an untracked instruction at " << SrcLine << RESET << "\n";
@@ -346,24 +473,26 @@ void RuntimeChecker::trackDebugLocDropping(
 
 void RuntimeChecker::trackInsertionImpl(
     Instruction *InsertInst, 
-    Instruction *InsertPosInst,
+    BasicBlock *InsertBB,
+    Instruction *InsertPosInst, /* Null if inserted into InsertBB at no given instruction */
     unsigned SrcLine
 ) {
 #ifdef DEBUG
     dbgs() << BLUE << "insertion at " << SrcLine << RESET 
-           << "\n\t" << *InsertInst << "\n\t" << *InsertPosInst << "\n";
+           << "\n\t" << *InsertInst << "\n\t" << InsertBB->getName() << "\n";
 #endif
 
     if (InstToDLDMap.contains(InsertInst)) {
         if (InstToDLDMap[InsertInst]->constructKind() == ConstructKind::Cloning) {
             // Determine whether the insertion position is dominated by the original 
             // instruction, from which the Inst is cloned from.
-            bool IsDominated = inDominantRegionOf(
-                InsertPosInst, 
-                InstToDLDMap[InsertInst]->originalInst()
-            );
+            Instruction *OriginalInst = InstToDLDMap[InsertInst]->originalInst();
+            bool IsDominated = InsertPosInst
+                ? inDominantRegionOf(InsertPosInst, OriginalInst)
+                : inDominantRegionOf(InsertBB, OriginalInst);
             InstToDLDMap[InsertInst]->insertAt(SrcLine, IsDominated);
         }
+        InstToDLDMap[InsertInst]->snapshot();
     }
 }
 
@@ -375,35 +504,80 @@ void RuntimeChecker::trackInsertion(
     std::string DLSName
 ) {
     Instruction *InsertInst = dyn_cast<Instruction>(InsertValue);
-    Instruction *InsertPosInst = dyn_cast<Instruction>(InsertPos);
+    if (!InsertInst || !InsertPos)
+        return ;
 
-    Instruction *DummyInst = nullptr;
-    if (!InsertPosInst) {
-        if (BasicBlock *BB = dyn_cast<BasicBlock>(InsertPos)) {
-            DummyInst = PHINode::Create(InsertInst->getType(), 0, "", BB);
-            InsertPosInst = DummyInst;
-        }
+    if (Instruction *InsertPosInst = dyn_cast<Instruction>(InsertPos)) {
+        if (InsertPosInst->getParent())
+            trackInsertionImpl(InsertInst, InsertPosInst->getParent(), InsertPosInst, SrcLine);
+    } else if (BasicBlock *InsertBB = dyn_cast<BasicBlock>(InsertPos)) {
+        trackInsertionImpl(InsertInst, InsertBB, nullptr, SrcLine);
     }
+}
+
+void RuntimeChecker::trackInsertion(
+    Instruction *InsertInst,
+    BasicBlock &InsertBB,
+    BasicBlock::iterator InsertPos,
+    unsigned SrcLine,
+    std::string DLDName,
+    std::string DLSName
+) {
+    // The end() of a block has no instruction to dereference, so the block stands for it
+    Instruction *InsertPosInst = InsertPos == InsertBB.end() ? nullptr : &*InsertPos;
+    trackInsertionImpl(InsertInst, &InsertBB, InsertPosInst, SrcLine);
+}
 
-    if (InsertInst && InsertPosInst)
-        trackInsertionImpl(InsertInst, InsertPosInst, SrcLine);
+void RuntimeChecker::trackInsertion(
+    Instruction *InsertInst,
+    BasicBlock::iterator InsertPos,
+    unsigned SrcLine,
+    std::string DLDName,
+    std::string DLSName
+) {
+    // Even the end() knows its block, which Instruction::insertBefore(InstListType::iterator)
+    // relies on as well
+    trackInsertion(InsertInst, *InsertPos->getParent(), InsertPos, SrcLine, DLDName, DLSName);
+}
+
+//===----------------------------------------------------------------------===//
+//                             Logs
+//===----------------------------------------------------------------------===//
+
+static std::string logDir() {
+    const char *Dir = std::getenv("DISAN_LOG_DIR");
+    return Dir && *Dir ? Dir : DISAN_LOG_DIR;
+}
 
-    if (DummyInst)
-        DummyInst->removeFromParent();
+static bool logPerProcess() {
+    const char *PerProcess = std::getenv("DISAN_LOG_PER_PROCESS");
+    if (PerProcess && *PerProcess)
+        return StringRef(PerProcess) != "0";
+    return DISAN_LOG_PER_PROCESS;
 }
 
-// void RuntimeChecker::trackInsertion(
-//     Value *InsertValue,
-//     BasicBlock::iterator InsertPos,
-//     unsigned SrcLine,
-//     std::string DLDName,
-//     std::string DLSName
-// ) {
-//     Instruction *InsertInst = dyn_cast<Instruction>(InsertValue);
-//     Instruction *InsertPosInst = &*InsertPos;
-//     if (InsertInst && InsertPosInst)
-//         trackInsertionImpl(InsertInst, InsertPosInst, SrcLine);
-// }
+raw_ostream *RuntimeChecker::openLogs(StringRef PassName) {
+    std::string Dir = logDir();
+    if (Dir == "-")
+        return &errs();
+
+    std::string Name = PassName.str();
+    if (logPerProcess())
+        Name += "." + std::to_string(sys::Process::getProcessId());
+    SmallString<128> FileName(Dir);
+    sys::path::append(FileName, Name + ".jsonl");
+
+    std::error_code ErrorCode = sys::fs::create_directories(Dir);
+    if (!ErrorCode) {
+        auto *File = new raw_fd_ostream(FileName, ErrorCode, sys::fs::OpenFlags::OF_Append);
+        if (!ErrorCode)
+            return File;
+        delete File;
+    }
+    errs() << "DISan: cannot open " << FileName << " (" << ErrorCode.message()
+           << "), logging to stderr\n";
+    return &errs();
+}
 
 //===----------------------------------------------------------------------===//
 //                             Main function
@@ -411,6 +585,9 @@ void RuntimeChecker::trackInsertion(
 
 void RuntimeChecker::startCheck() {
     for (auto [DebugLocDst, DLDM]: InstToDLDMap) {
-        logs() << DLDM->toString() << "\n";
+#ifdef DEBUG
+        dbgs() << DLDM->toString() << "\n";
+#endif
+        logs() << DLDM->toJSON(ModuleName, FunctionName) << "\n";
     }
 }
\ No newline at end of file
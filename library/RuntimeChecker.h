@@ -1,16 +1,35 @@
 #ifndef LLVM_TRANSFORM_UTILS_RUNTIME_DEBUGLOC_CHECKER_H
 #define LLVM_TRANSFORM_UTILS_RUNTIME_DEBUGLOC_CHECKER_H
 
+#include "llvm/ADT/ScopeExit.h"
 #include "llvm/IR/PassManager.h"
 #include "llvm/IR/Dominators.h"
 #include "llvm/Analysis/PostDominators.h"
 #include "llvm/Support/FileSystem.h"
+#include "llvm/Support/JSON.h"
 #include "llvm/Analysis/LoopInfo.h"
 #include "llvm/Analysis/LoopAnalysisManager.h"
 #include "llvm/Analysis/LoopNestAnalysis.h"
 
+#include <optional>
+
 using namespace llvm;
 
+// Version of the JSON Lines log schema, which must match LOG_SCHEMA_VERSION in DISan
+#define DISAN_LOG_SCHEMA 1
+
+// Version of the hook signatures, which must match RUNTIME_ABI_VERSION in DISan.
+// The instrumented passes static_assert on it.
+#define DISAN_RUNTIME_ABI 3
+
+// Directory of the logs, set by `disan install-runtime --log-dir` and overridden
+// by the DISAN_LOG_DIR environment variable. "-" sends the logs to stderr.
+#define DISAN_LOG_DIR "/tmp/disan/"
+
+// Whether to name the logs by process id as well, so that parallel lit workers
+// append to their own files. Overridden by DISAN_LOG_PER_PROCESS=0/1.
+#define DISAN_LOG_PER_PROCESS 0
+
 enum class UpdateKind {
     Preserving,
     Merging,
@@ -26,6 +45,16 @@ enum class ConstructKind {
     Untracked,
 };
 
+/// The IR of an instruction at a hook, printed then since it may be erased later
+struct IRContext {
+    unsigned Site;      // 0 for the destination itself
+    std::string Name;   // The source expression, e.g., `NewI`
+    std::string IR;
+    std::string Loc;    // The DILocation, i.e., `file:line:col`, or empty if none
+
+    json::Value toJSON() const;
+};
+
 class DebugLocDstM {
 public:
     DebugLocDstM(std::string VN, unsigned CS, ConstructKind CK, Instruction *Inst)
@@ -73,8 +102,17 @@ public:
         InCodeUpdateSite = US;
     }
 
+    /// Refresh the printed IR of the destination, keeping its original DILocation
+    void snapshot(StringRef Name = "");
+    /// Record a replaced instruction, or the original one of a clone
+    void addSource(unsigned Site, StringRef Name, Instruction *Src);
+
     std::string toString();
+    json::Value toJSON(StringRef ModuleName, StringRef FunctionName);
 private:
+    StringRef verdict(UpdateKind ProperKind) const;
+    SmallVector<unsigned, 2> sortedReplaceSites() const;
+
     std::string VarName;
     Instruction *TheInst;
 
@@ -95,6 +133,11 @@ private:
     /* Update track */
     UpdateKind InCodeUpdateKind;
     unsigned InCodeUpdateSite;
+
+    /* IR context */
+    std::string DstFunction;
+    std::optional<IRContext> DstContext;
+    SmallVector<IRContext, 2> SrcContexts;
 };
 
 class RuntimeChecker {
@@ -104,15 +147,9 @@ public:
           ModuleName(F.getParent()->getName()), 
           FunctionName(F.getName()),
           DT(new DominatorTree(F)),
-          PDT(new PostDominatorTree(F))
-    {
-        StringRef DirName = "/data16/hshan/tmp/";
-        sys::fs::create_directories(DirName);
-
-        std::error_code ErrorCode;
-        Twine FileName = DirName + PassName;
-        Logs = new raw_fd_ostream(FileName.str(), ErrorCode, sys::fs::OpenFlags::OF_Append);
-    }
+          PDT(new PostDominatorTree(F)),
+          Logs(openLogs(PN))
+    {}
 
     RuntimeChecker(Loop &L, StringRef PN)
         : RuntimeChecker(*L.getHeader()->getParent(), PN) {}
@@ -125,8 +162,31 @@ public:
         Value *ExtraValue,
         ConstructKind Kind, 
         unsigned SrcLine, 
-        std::string DLDName,    /* Obsoleted */
-        std::string IPName      /* Obsoleted */
+        std::string DLDName,
+        std::string IPName
+    );
+
+    // Moves to an iterator of a block, e.g., `I->moveBefore(BB, It)`, which may
+    // be its end(). The block is taken by reference so that the creations
+    // passing a null ExtraValue never resolve to this overload.
+    void trackDebugLocDst(
+        Instruction *DebugLocDst,
+        BasicBlock &MoveBB,
+        BasicBlock::iterator MovePos,
+        ConstructKind Kind,
+        unsigned SrcLine,
+        std::string DLDName,
+        std::string IPName
+    );
+
+    // Moves to an iterator alone, e.g., `I->moveBefore(It)`
+    void trackDebugLocDst(
+        Instruction *DebugLocDst,
+        BasicBlock::iterator MovePos,
+        ConstructKind Kind,
+        unsigned SrcLine,
+        std::string DLDName,
+        std::string IPName
     );
 
     void trackDebugLocSrc(
@@ -169,10 +229,31 @@ public:
         std::string DLSName = ""
     );
 
+    // Insertions at an iterator of a block, e.g., `I->insertInto(BB, It)`,
+    // which may be its end()
+    void trackInsertion(
+        Instruction *InsertInst,
+        BasicBlock &InsertBB,
+        BasicBlock::iterator InsertPos,
+        unsigned SrcLine,
+        std::string DLDName = "",
+        std::string DLSName = ""
+    );
+
+    // Insertions at an iterator alone, e.g., `I->insertBefore(It)`
+    void trackInsertion(
+        Instruction *InsertInst,
+        BasicBlock::iterator InsertPos,
+        unsigned SrcLine,
+        std::string DLDName = "",
+        std::string DLSName = ""
+    );
+
     void startCheck();
 
     ~RuntimeChecker() {
-        delete Logs;
+        if (Logs != &errs())
+            delete Logs;
         for (auto [_, DLDM]: InstToDLDMap) {
             if (DLDM)
                 delete DLDM;
@@ -189,23 +270,30 @@ private:
     PostDominatorTree *PDT;
     DenseMap<Instruction *, DebugLocDstM *> InstToDLDMap;
 
-    raw_fd_ostream *Logs;
+    raw_ostream *Logs;
+
+    raw_ostream &logs() { return *Logs; }
 
-    raw_fd_ostream &logs() { return *Logs; }
+    /// Open `<pass>.jsonl` (or `<pass>.<pid>.jsonl`) in the log directory, or stderr
+    static raw_ostream *openLogs(StringRef PassName);
 
     /* Simple Queries */
     bool inDominantRegionOf(Instruction *DebugLocDst, Instruction *DebugLocSrc);
+    bool inDominantRegionOf(BasicBlock *DebugLocDstBB, Instruction *DebugLocSrc);
 
     /* Main functionality implementations */
     void trackDebugLocDstImpl(
         Instruction *DebugLocDstInst,
         Value *ExtraValue,
         ConstructKind Kind,
-        unsigned SrcLine
+        unsigned SrcLine,
+        StringRef DLDName,
+        StringRef ExtraName
     );
     void trackDebugLocUpdateImpl();
     void trackInsertionImpl(
         Instruction *Inst,
+        BasicBlock *InsertBB,
         Instruction *InsertPosInst,
         unsigned SrcLine
     );
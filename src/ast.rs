@@ -1,6 +1,7 @@
 use colored::Colorize;
 use tree_sitter::Node;
 
+#[allow(dead_code)]
 pub trait AstNode {
     fn row(&self) -> usize;
 
@@ -24,7 +25,7 @@ impl<'tree> AstNode for Node<'tree> {
     }
 
     fn to_source(&self, code: &str) -> String {
-        let source: Vec<&str> = (&code[self.start_byte()..self.end_byte()])
+        let source: Vec<&str> = code[self.start_byte()..self.end_byte()]
             .split("\n")
             .map(|s| s.trim())
             .collect();
@@ -62,6 +63,7 @@ pub enum ASTNodeKind {
     CallExpr,
     NewExpr,
     FieldExpr,
+    LambdaExpr,
 }
 
 impl ASTNodeKind {
@@ -73,13 +75,14 @@ impl ASTNodeKind {
             ASTNodeKind::CallExpr => "call_expression",
             ASTNodeKind::NewExpr => "new_expression",
             ASTNodeKind::FieldExpr => "field_expression",
+            ASTNodeKind::LambdaExpr => "lambda_expression",
         }
     }
 }
 
-impl Into<&str> for ASTNodeKind {
-    fn into(self) -> &'static str {
-        self.to_string()
+impl From<ASTNodeKind> for &str {
+    fn from(kind: ASTNodeKind) -> Self {
+        kind.to_string()
     }
 }
 
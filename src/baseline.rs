@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::fingerprint::RecordKey;
+use crate::log::{LogRecord, Verdict};
+use crate::matcher::DLUpdateKind;
+use crate::source::{PassSource, SourceRoot};
+
+/// Version of the baseline file format
+const BASELINE_VERSION: u32 = 1;
+
+/// A known violation, keyed by its pass, construct site, replace sites and
+/// expected update kind
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    #[serde(flatten)]
+    pub key: RecordKey,
+    pub expected: DLUpdateKind,
+}
+
+impl BaselineEntry {
+    pub fn of(record: &LogRecord, source: Option<&PassSource>) -> Self {
+        Self {
+            key: RecordKey::of(record, source),
+            expected: record.expected,
+        }
+    }
+
+    pub fn matches(&self, other: &BaselineEntry) -> bool {
+        self.expected == other.expected && self.key.matches(&other.key)
+    }
+}
+
+/// Known violations suppressed in the reports
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Baseline {
+    pub version: u32,
+    pub entries: Vec<BaselineEntry>,
+}
+
+impl Baseline {
+    /// Take the fail and warn records as the known violations
+    pub fn from_records(records: &[LogRecord], mut source_root: Option<&mut SourceRoot>) -> Self {
+        let mut entries: Vec<BaselineEntry> = vec![];
+        for record in records.iter().filter(|r| r.verdict != Verdict::Pass) {
+            let source = source_root
+                .as_deref_mut()
+                .and_then(|source_root| source_root.get(&record.pass));
+            let entry = BaselineEntry::of(record, source);
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+        Self {
+            version: BASELINE_VERSION,
+            entries,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let baseline: Baseline = serde_json::from_str(&content)
+            .map_err(|e| format!("Malformed baseline {}: {}", path.display(), e))?;
+        if baseline.version > BASELINE_VERSION {
+            return Err(format!(
+                "Unsupported baseline version {} of {}, please update DISan",
+                baseline.version,
+                path.display()
+            ));
+        }
+        Ok(baseline)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        fs::write(path, content + "\n")
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Keep the new violations, i.e., the fail and warn records missing from the
+    /// baseline, returning them and the number of distinct violations suppressed
+    pub fn filter(
+        &self,
+        records: Vec<LogRecord>,
+        mut source_root: Option<&mut SourceRoot>,
+    ) -> (Vec<LogRecord>, usize) {
+        let mut suppressed: Vec<String> = vec![];
+        let mut kept = vec![];
+        for record in records.into_iter().filter(|r| r.verdict != Verdict::Pass) {
+            let source = source_root
+                .as_deref_mut()
+                .and_then(|source_root| source_root.get(&record.pass));
+            let entry = BaselineEntry::of(&record, source);
+            if self.entries.iter().any(|e| e.matches(&entry)) {
+                let key = record.to_string();
+                if !suppressed.contains(&key) {
+                    suppressed.push(key);
+                }
+            } else {
+                kept.push(record);
+            }
+        }
+        (kept, suppressed.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<LogRecord> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn known_violations_only() {
+        let baseline = Baseline::from_records(
+            &records(&[
+                "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+                "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+                "warn: Drop [Construct: 20, Move; Pass: Foo.cpp]",
+                "pass: Preserve [Construct: 30, Create; Update: 31, Preserve; Pass: Foo.cpp]",
+            ]),
+            None,
+        );
+        assert_eq!(baseline.entries.len(), 2);
+    }
+
+    #[test]
+    fn filter_keeps_new_violations() {
+        let baseline = Baseline::from_records(
+            &records(&[
+                "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+                "warn: Drop [Construct: 20, Move; Pass: Foo.cpp]",
+            ]),
+            None,
+        );
+        let (kept, suppressed) = baseline.filter(
+            records(&[
+                "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+                "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+                /* Another expected update is another violation */
+                "warn: Merge [Construct: 20, Move; Pass: Foo.cpp]",
+                "warn: Drop [Construct: 40, Move; Pass: Foo.cpp]",
+                "pass: Preserve [Construct: 30, Create; Update: 31, Preserve; Pass: Foo.cpp]",
+            ]),
+            None,
+        );
+        assert_eq!(suppressed, 1);
+        let sites: Vec<usize> = kept.iter().map(|record| record.construct_site).collect();
+        assert_eq!(sites, [20, 40]);
+    }
+}
@@ -0,0 +1,223 @@
+use colored::Colorize;
+use std::collections::BTreeMap;
+
+use crate::fingerprint::RecordKey;
+use crate::log::{LogRecord, Verdict};
+use crate::source::SourceRoot;
+
+/// How a record differs between two runs
+pub enum Change<'r> {
+    /// A violation only in the new run
+    New(&'r LogRecord),
+    /// A violation only in the old run
+    Fixed(&'r LogRecord),
+    /// The same destination with a different verdict or expected update, (old, new)
+    Changed(&'r LogRecord, &'r LogRecord),
+}
+
+fn is_violation(record: &LogRecord) -> bool {
+    record.verdict != Verdict::Pass
+}
+
+fn keys_of(records: &[LogRecord], mut source_root: Option<&mut SourceRoot>) -> Vec<RecordKey> {
+    records
+        .iter()
+        .map(|record| {
+            let source = source_root
+                .as_deref_mut()
+                .and_then(|source_root| source_root.get(&record.pass));
+            RecordKey::of(record, source)
+        })
+        .collect()
+}
+
+/// Align the records of the two runs by pass and by the fingerprints of their
+/// sites (or the line numbers, for the runs without source roots), and collect
+/// the changes in between. Records are paired with the identical ones first.
+pub fn diff_runs<'r>(
+    old: &'r [LogRecord],
+    old_root: Option<&mut SourceRoot>,
+    new: &'r [LogRecord],
+    new_root: Option<&mut SourceRoot>,
+) -> Vec<Change<'r>> {
+    let old_keys = keys_of(old, old_root);
+    let new_keys = keys_of(new, new_root);
+
+    let mut old_paired = vec![false; old.len()];
+    let mut new_paired: Vec<Option<usize>> = vec![None; new.len()];
+    let same_outcome = |o: &LogRecord, n: &LogRecord| {
+        o.verdict == n.verdict && o.expected == n.expected && o.update == n.update
+    };
+    for exact in [true, false] {
+        for (n, new_key) in new_keys.iter().enumerate() {
+            if new_paired[n].is_some() {
+                continue;
+            }
+            let paired = (0..old.len()).find(|&o| {
+                !old_paired[o]
+                    && old_keys[o].matches(new_key)
+                    && (!exact || same_outcome(&old[o], &new[n]))
+            });
+            if let Some(o) = paired {
+                old_paired[o] = true;
+                new_paired[n] = Some(o);
+            }
+        }
+    }
+
+    let mut changes = vec![];
+    for (n, paired) in new_paired.iter().enumerate() {
+        match paired {
+            None if is_violation(&new[n]) => changes.push(Change::New(&new[n])),
+            Some(o) if old[*o].verdict != new[n].verdict || old[*o].expected != new[n].expected => {
+                changes.push(Change::Changed(&old[*o], &new[n]))
+            }
+            _ => {}
+        }
+    }
+    for (o, paired) in old_paired.iter().enumerate() {
+        if !paired && is_violation(&old[o]) {
+            changes.push(Change::Fixed(&old[o]));
+        }
+    }
+    changes
+}
+
+/// Per-pass statistics of the two runs
+#[derive(Default)]
+struct PassStats {
+    old_fails: usize,
+    new_fails: usize,
+    new: usize,
+    fixed: usize,
+    changed: usize,
+}
+
+/// Passes of the fail records, one per record
+fn fails(records: &[LogRecord]) -> impl Iterator<Item = &str> {
+    records
+        .iter()
+        .filter(|record| record.verdict == Verdict::Fail)
+        .map(|record| record.pass.as_str())
+}
+
+fn print_section(title: &str, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    println!("{} ({})", title.bold(), lines.len());
+    for line in lines {
+        println!("  {}", line);
+    }
+    println!();
+}
+
+/// Print the new, fixed and changed violations followed by the per-pass statistics
+pub fn print_diff<'r>(old: &'r [LogRecord], new: &'r [LogRecord], changes: &[Change<'r>]) {
+    let mut stats: BTreeMap<&str, PassStats> = BTreeMap::new();
+    for pass in fails(old) {
+        stats.entry(pass).or_default().old_fails += 1;
+    }
+    for pass in fails(new) {
+        stats.entry(pass).or_default().new_fails += 1;
+    }
+
+    let (mut new_lines, mut fixed_lines, mut changed_lines) = (vec![], vec![], vec![]);
+    for change in changes {
+        match change {
+            Change::New(record) => {
+                stats.entry(&record.pass).or_default().new += 1;
+                new_lines.push(format!("{} {}", "+".green().bold(), record));
+            }
+            Change::Fixed(record) => {
+                stats.entry(&record.pass).or_default().fixed += 1;
+                fixed_lines.push(format!("{} {}", "-".red().bold(), record));
+            }
+            Change::Changed(old_record, new_record) => {
+                stats.entry(&new_record.pass).or_default().changed += 1;
+                changed_lines.push(format!(
+                    "{} {}:{} (was {}): {} {} -> {} {}",
+                    "~".yellow().bold(),
+                    new_record.pass,
+                    new_record.construct_site,
+                    old_record.construct_site,
+                    old_record.verdict.log_name(),
+                    old_record.expected.log_name(),
+                    new_record.verdict.log_name(),
+                    new_record.expected.log_name(),
+                ));
+            }
+        }
+    }
+    print_section("New violations", &new_lines);
+    print_section("Fixed violations", &fixed_lines);
+    print_section("Changed verdicts", &changed_lines);
+
+    let width = stats
+        .keys()
+        .map(|pass| pass.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "{:<width$}  {:>9}  {:>9}  {:>5}  {:>5}  {:>7}",
+        "Pass", "Old fails", "New fails", "New", "Fixed", "Changed"
+    );
+    for (pass, stat) in &stats {
+        println!(
+            "{:<width$}  {:>9}  {:>9}  {:>5}  {:>5}  {:>7}",
+            pass, stat.old_fails, stat.new_fails, stat.new, stat.fixed, stat.changed
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<LogRecord> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn classify_changes() {
+        let old = records(&[
+            "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+            "fail: Drop [Construct: 20, Move; Pass: Foo.cpp]",
+            "warn: Merge [Construct: 30, Clone; Replace: 31, 32; Pass: Foo.cpp]",
+            "pass: Preserve [Construct: 40, Create; Update: 41, Preserve; Pass: Foo.cpp]",
+        ]);
+        let new = records(&[
+            "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+            "fail: Merge [Construct: 30, Clone; Replace: 32, 31; Pass: Foo.cpp]",
+            "pass: Preserve [Construct: 40, Create; Update: 41, Preserve; Pass: Foo.cpp]",
+            "fail: Preserve [Construct: 50, Create; Pass: Foo.cpp]",
+            "pass: Preserve [Construct: 60, Create; Update: 61, Preserve; Pass: Foo.cpp]",
+        ]);
+        let changes = diff_runs(&old, None, &new, None);
+        let sites: Vec<(&str, usize)> = changes
+            .iter()
+            .map(|change| match change {
+                Change::New(record) => ("new", record.construct_site),
+                Change::Fixed(record) => ("fixed", record.construct_site),
+                Change::Changed(old, new) => {
+                    assert_eq!((old.verdict, new.verdict), (Verdict::Warn, Verdict::Fail));
+                    ("changed", new.construct_site)
+                }
+            })
+            .collect();
+        assert_eq!(sites, [("changed", 30), ("new", 50), ("fixed", 20)]);
+    }
+
+    #[test]
+    fn pair_identical_records_first() {
+        let old = records(&[
+            "warn: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+            "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+        ]);
+        let new = records(&["fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]"]);
+        let changes = diff_runs(&old, None, &new, None);
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0], Change::Fixed(record) if record.verdict == Verdict::Warn));
+    }
+}
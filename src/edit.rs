@@ -1,9 +1,12 @@
-#[derive(PartialEq)]
+use colored::Colorize;
+
+#[derive(Clone, PartialEq)]
 pub enum EditKind {
     Insert,
     Replace(usize),
 }
 
+#[derive(Clone)]
 pub struct Edit {
     pub content: String,
     pub start_pos: usize,
@@ -27,3 +30,39 @@ impl Edit {
         }
     }
 }
+
+/// Apply the edits to the original code in a single forward pass.
+///
+/// Edits at the same position keep the order they were collected in, with
+/// insertions going before replacements. Since outer constructs are visited
+/// before the constructs nested in them, the wrappers of the former enclose
+/// the wrappers of the latter. Replacements overlapping a previous edit are
+/// dropped with a warning instead of mangling the code.
+pub fn apply_edits(code: &str, edits: &[Edit]) -> String {
+    let mut ordered: Vec<&Edit> = edits.iter().collect();
+    ordered.sort_by_key(|edit| (edit.start_pos, edit.kind != EditKind::Insert));
+
+    let mut buf = String::with_capacity(code.len());
+    let mut cursor = 0;
+    for edit in ordered {
+        if edit.start_pos < cursor {
+            println!(
+                "{}{} {}",
+                "warning".yellow().bold(),
+                ": Drop an edit overlapping the previous one at byte".bold(),
+                edit.start_pos,
+            );
+            continue;
+        }
+
+        buf.push_str(&code[cursor..edit.start_pos]);
+        buf.push_str(&edit.content);
+        cursor = match edit.kind {
+            EditKind::Insert => edit.start_pos,
+            EditKind::Replace(end_pos) => end_pos,
+        };
+    }
+    buf.push_str(&code[cursor..]);
+
+    buf
+}
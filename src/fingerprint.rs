@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+use crate::log::LogRecord;
+use crate::source::PassSource;
+
+/// A source site, identified by the fingerprint of its line if the pass source
+/// was found, or else by its line number
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SiteKey {
+    pub site: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+impl SiteKey {
+    pub fn new(site: usize, source: Option<&PassSource>) -> Self {
+        Self {
+            site,
+            fingerprint: source.and_then(|source| source.fingerprint(site)),
+        }
+    }
+
+    /// Fingerprints are compared if both sides have them, so that the line
+    /// drift does not matter, and line numbers otherwise
+    pub fn matches(&self, other: &SiteKey) -> bool {
+        match (&self.fingerprint, &other.fingerprint) {
+            (Some(fingerprint), Some(other_fingerprint)) => fingerprint == other_fingerprint,
+            _ => self.site == other.site,
+        }
+    }
+}
+
+/// Identity of a record across runs and source revisions, i.e., its pass,
+/// construct site and replace sites
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordKey {
+    pub pass: String,
+    pub construct: SiteKey,
+    pub replace: Vec<SiteKey>,
+}
+
+impl RecordKey {
+    pub fn of(record: &LogRecord, source: Option<&PassSource>) -> Self {
+        Self {
+            pass: record.pass.clone(),
+            construct: SiteKey::new(record.construct_site, source),
+            replace: record
+                .replace_sites
+                .iter()
+                .map(|&site| SiteKey::new(site, source))
+                .collect(),
+        }
+    }
+
+    pub fn matches(&self, other: &RecordKey) -> bool {
+        self.pass == other.pass
+            && self.construct.matches(&other.construct)
+            && self.replace.len() == other.replace.len()
+            && self
+                .replace
+                .iter()
+                .all(|site| other.replace.iter().any(|other| site.matches(other)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(code: &str) -> PassSource {
+        PassSource {
+            path: "Foo.cpp".into(),
+            code: code.to_string(),
+        }
+    }
+
+    #[test]
+    fn fingerprints_survive_line_drift() {
+        let old = source("void f() {\n  I->moveBefore(J);\n}\n");
+        let new = source("// A new line\nvoid f() {\n    I->moveBefore(J);  \n}\n");
+        let old_key = SiteKey::new(2, Some(&old));
+        assert!(old_key.matches(&SiteKey::new(3, Some(&new))));
+        assert!(!old_key.matches(&SiteKey::new(2, Some(&new))));
+    }
+
+    #[test]
+    fn line_numbers_without_sources() {
+        let old = source("void f() {\n  I->moveBefore(J);\n}\n");
+        assert!(SiteKey::new(2, Some(&old)).matches(&SiteKey::new(2, None)));
+        assert!(!SiteKey::new(2, None).matches(&SiteKey::new(3, None)));
+    }
+
+    #[test]
+    fn replace_sites_in_any_order() {
+        let record = |line: &str| line.parse::<LogRecord>().unwrap();
+        let key = RecordKey::of(
+            &record("fail: Preserve [Construct: 1, Create; Replace: 2, 3; Pass: Foo.cpp]"),
+            None,
+        );
+        let other = RecordKey::of(
+            &record("warn: Merge [Construct: 1, Clone; Replace: 3, 2; Pass: Foo.cpp]"),
+            None,
+        );
+        assert!(key.matches(&other));
+        let fewer = RecordKey::of(
+            &record("fail: Preserve [Construct: 1, Create; Replace: 2; Pass: Foo.cpp]"),
+            None,
+        );
+        assert!(!key.matches(&fewer));
+        let elsewhere = RecordKey::of(
+            &record("fail: Preserve [Construct: 1, Create; Replace: 2, 3; Pass: Bar.cpp]"),
+            None,
+        );
+        assert!(!key.matches(&elsewhere));
+    }
+}
@@ -1,3 +1,6 @@
+/// Version of the hook signatures, which must match `DISAN_RUNTIME_ABI` in the runtime library
+pub const RUNTIME_ABI_VERSION: u32 = 3;
+
 pub struct Hook;
 
 impl Hook {
@@ -5,6 +8,15 @@ impl Hook {
         "#include \"llvm/Transforms/Utils/RuntimeChecker.h\"\n"
     }
 
+    /// Fail the build of an instrumented pass early against a mismatched runtime
+    pub fn abi_check() -> String {
+        format!(
+            "static_assert(DISAN_RUNTIME_ABI == {}, \"The installed RuntimeChecker.h mismatches DISan, \
+             rerun `disan install-runtime`\");\n",
+            RUNTIME_ABI_VERSION
+        )
+    }
+
     pub fn global_var_decl() -> &'static str {
         "namespace { RuntimeChecker *RC = nullptr; }\n"
     }
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+
+use crate::log::IrContext;
+use crate::log::{LogRecord, Verdict};
+use crate::matcher::{ConstructKind, DLUpdateKind};
+use crate::report::{describe_ir_value, labeled_sites, violation_message};
+use crate::source::{PassSource, SourceRoot};
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.5em; }
+h2 { font-size: 1.2em; margin-top: 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.8em; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+.filters { margin: 1em 0; }
+.filters label { margin-right: 1.5em; }
+details { border: 1px solid #ddd; border-radius: 4px; margin: 0.4em 0; padding: 0.3em 0.6em; }
+summary { cursor: pointer; }
+.verdict { font-weight: bold; text-transform: uppercase; margin-right: 0.5em; }
+.pass { color: #2a7d2a; }
+.warn { color: #b8860b; }
+.fail { color: #c0392b; }
+.meta { color: #666; margin: 0.4em 0; }
+pre { background: #f6f8fa; padding: 0.5em; overflow-x: auto; }
+.lineno { color: #999; user-select: none; }
+.label { color: #c0392b; font-weight: bold; }
+.gap { color: #999; }
+"#;
+
+const SCRIPT: &str = r#"
+function applyFilters() {
+  const update = document.getElementById('filter-update').value;
+  const construct = document.getElementById('filter-construct').value;
+  const verdict = document.getElementById('filter-verdict').value;
+  for (const entry of document.querySelectorAll('details.entry')) {
+    const shown = (!update || entry.dataset.update === update)
+      && (!construct || entry.dataset.construct === construct)
+      && (!verdict || entry.dataset.verdict === verdict);
+    entry.style.display = shown ? '' : 'none';
+  }
+  for (const section of document.querySelectorAll('section.pass-section')) {
+    const visible = section.querySelectorAll('details.entry:not([style*="none"])').length;
+    section.querySelector('.shown').textContent = visible;
+  }
+}
+for (const select of document.querySelectorAll('.filters select')) {
+  select.addEventListener('change', applyFilters);
+}
+applyFilters();
+"#;
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn count(records: &[&LogRecord], verdict: Verdict) -> usize {
+    records.iter().filter(|r| r.verdict == verdict).count()
+}
+
+fn select(id: &str, title: &str, options: &[&str]) -> String {
+    let options: String = options
+        .iter()
+        .map(|option| format!("<option value=\"{0}\">{0}</option>", option))
+        .collect();
+    format!(
+        "<label>{} <select id=\"{}\"><option value=\"\">All</option>{}</select></label>",
+        title, id, options
+    )
+}
+
+/// The lines of the sites, with the gaps between them elided
+fn snippet(record: &LogRecord, source: &PassSource) -> String {
+    let sites = labeled_sites(record);
+    let gutter = sites.last().map_or(1, |(site, _)| site.to_string().len());
+    let mut html = String::from("<pre>");
+    let mut last_site = None;
+    for (site, label) in &sites {
+        if last_site.is_some_and(|last| site > &(last + 1)) {
+            html += "<span class=\"gap\">...</span>\n";
+        }
+        if last_site != Some(*site) {
+            html += &format!(
+                "<span class=\"lineno\">{:>gutter$} | </span>{}",
+                site,
+                escape_html(source.line(*site).unwrap_or("<no such line>"))
+            );
+        }
+        html += &format!("  <span class=\"label\">// {}</span>\n", escape_html(label));
+        last_site = Some(*site);
+    }
+    html + "</pre>"
+}
+
+/// The IR of the destination and its sources as printed by the runtime
+fn ir_context(context: &IrContext) -> String {
+    let mut html = String::from("<div class=\"meta\">IR context:</div>\n<pre>");
+    if let Some(dst) = &context.dst {
+        html += &format!(
+            "<span class=\"label\">; destination {} in function {}</span>\n{}\n",
+            escape_html(&describe_ir_value(dst)),
+            escape_html(&context.function),
+            escape_html(&dst.ir)
+        );
+    }
+    for src in &context.srcs {
+        let site = src
+            .site
+            .map_or(String::new(), |site| format!(" (line {})", site));
+        html += &format!(
+            "<span class=\"label\">; source {}{}</span>\n{}\n",
+            escape_html(&describe_ir_value(src)),
+            site,
+            escape_html(&src.ir)
+        );
+    }
+    html + "</pre>"
+}
+
+fn entry(record: &LogRecord, source: Option<&PassSource>) -> String {
+    let verdict = record.verdict.log_name();
+    let mut html = format!(
+        "<details class=\"entry\" data-verdict=\"{}\" data-update=\"{}\" data-construct=\"{}\">\n",
+        verdict,
+        record.expected.log_name(),
+        record.construct_kind.log_name()
+    );
+    html += &format!(
+        "<summary><span class=\"verdict {}\">{}</span>{}:{} {}</summary>\n",
+        verdict,
+        verdict,
+        escape_html(&record.pass),
+        record.construct_site,
+        escape_html(&violation_message(record))
+    );
+    html += &format!(
+        "<div class=\"meta\">The proper update is {} for the instruction constructed by {}.",
+        record.expected,
+        record.construct_kind.log_name()
+    );
+    if let (Some(module), Some(function)) = (&record.module, &record.function) {
+        html += &format!(
+            " Checked in function <code>{}</code> of module <code>{}</code>.",
+            escape_html(function),
+            escape_html(module)
+        );
+    }
+    html += "</div>\n";
+    html += &match source {
+        Some(source) => snippet(record, source),
+        None => format!("<pre>{}</pre>", escape_html(&record.to_string())),
+    };
+    if let Some(context) = &record.context {
+        html += &ir_context(context);
+    }
+    html + "\n</details>\n"
+}
+
+/// Render the records as a self-contained HTML page, with the counts and the
+/// violations of each pass and filters by update and construct kinds. The
+/// violations are shown with source snippets if the pass sources are found.
+pub fn to_html(records: &[LogRecord], mut source_root: Option<&mut SourceRoot>) -> String {
+    let mut by_pass: BTreeMap<&str, Vec<&LogRecord>> = BTreeMap::new();
+    for record in records {
+        by_pass.entry(&record.pass).or_default().push(record);
+    }
+
+    let mut summary = String::from(
+        "<table>\n<tr><th>Pass source</th><th>Pass</th><th>Warn</th><th>Fail</th></tr>\n",
+    );
+    let mut sections = String::new();
+    for (pass, records) in &by_pass {
+        summary += &format!(
+            "<tr><td><a href=\"#{0}\">{0}</a></td><td class=\"pass\">{1}</td><td class=\"warn\">{2}</td><td class=\"fail\">{3}</td></tr>\n",
+            escape_html(pass),
+            count(records, Verdict::Pass),
+            count(records, Verdict::Warn),
+            count(records, Verdict::Fail)
+        );
+
+        let violations: Vec<&&LogRecord> = records
+            .iter()
+            .filter(|record| record.verdict != Verdict::Pass)
+            .collect();
+        sections += &format!(
+            "<section class=\"pass-section\" id=\"{0}\">\n<h2>{0}</h2>\n<p>Showing <span class=\"shown\">{1}</span> of {1} violations</p>\n",
+            escape_html(pass),
+            violations.len()
+        );
+        let source = source_root
+            .as_deref_mut()
+            .and_then(|source_root| source_root.get(pass));
+        for record in violations {
+            sections += &entry(record, source);
+        }
+        sections += "</section>\n";
+    }
+    summary += &format!(
+        "<tr><th>Total</th><th class=\"pass\">{}</th><th class=\"warn\">{}</th><th class=\"fail\">{}</th></tr>\n</table>\n",
+        records.iter().filter(|r| r.verdict == Verdict::Pass).count(),
+        records.iter().filter(|r| r.verdict == Verdict::Warn).count(),
+        records.iter().filter(|r| r.verdict == Verdict::Fail).count()
+    );
+
+    let update_kinds = [
+        DLUpdateKind::Preserving,
+        DLUpdateKind::Merging,
+        DLUpdateKind::Dropping,
+        DLUpdateKind::Others,
+    ]
+    .map(|kind| kind.log_name());
+    let construct_kinds = [
+        ConstructKind::Creating,
+        ConstructKind::Cloning,
+        ConstructKind::Moving,
+        ConstructKind::Untracked,
+    ]
+    .map(|kind| kind.log_name());
+    let filters = format!(
+        "<div class=\"filters\">{}{}{}</div>\n",
+        select("filter-update", "Proper update", &update_kinds),
+        select("filter-construct", "Construct", &construct_kinds),
+        select("filter-verdict", "Verdict", &["warn", "fail"]),
+    );
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>DISan report</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1>DISan report</h1>\n{}{}{}<script>{}</script>\n</body>\n</html>\n",
+        STYLE, summary, filters, sections, SCRIPT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::IrValue;
+
+    fn records(lines: &[&str]) -> Vec<LogRecord> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_html(r#"<b x="&">"#), "&lt;b x=&quot;&amp;&quot;&gt;");
+    }
+
+    #[test]
+    fn counts_and_violations() {
+        let html = to_html(
+            &records(&[
+                "fail: Preserve [Construct: 10, Create; Pass: <Foo>.cpp]",
+                "warn: Drop [Construct: 20, Move; Pass: <Foo>.cpp]",
+                "pass: Preserve [Construct: 30, Create; Update: 31, Preserve; Pass: <Foo>.cpp]",
+            ]),
+            None,
+        );
+        assert!(!html.contains("<Foo>"));
+        assert!(html.contains(
+            "<td class=\"pass\">1</td><td class=\"warn\">1</td><td class=\"fail\">1</td>"
+        ));
+        assert!(html.contains("Showing <span class=\"shown\">2</span> of 2 violations"));
+        assert_eq!(html.matches("<details class=\"entry\"").count(), 2);
+        assert!(html
+            .contains("data-verdict=\"fail\" data-update=\"Preserve\" data-construct=\"Create\""));
+    }
+
+    #[test]
+    fn ir_context_is_escaped() {
+        let value = |name: &str, ir: &str, loc: Option<&str>, site: Option<usize>| IrValue {
+            name: name.to_string(),
+            ir: ir.to_string(),
+            loc: loc.map(str::to_string),
+            site,
+        };
+        let context = IrContext {
+            function: "f<int>".to_string(),
+            dst: Some(value(
+                "NewI",
+                "%x = load <2 x i32>, ptr %p",
+                Some("a.c:3:5"),
+                None,
+            )),
+            srcs: vec![value("", "%y = add i32 %a, %b", None, Some(12))],
+        };
+        let html = ir_context(&context);
+        assert!(html.contains("; destination `NewI` at a.c:3:5 in function f&lt;int&gt;"));
+        assert!(html.contains("%x = load &lt;2 x i32&gt;, ptr %p"));
+        assert!(html.contains("; source `&lt;unnamed&gt;` without location (line 12)"));
+    }
+}
@@ -1,15 +1,100 @@
 use colored::Colorize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use tree_sitter::{Node, Parser};
 
 use crate::ast::{ASTNodeKind, AstNode};
-use crate::edit::{Edit, EditKind};
+use crate::edit::{apply_edits, Edit};
 use crate::hook::Hook;
 use crate::matcher::{ConstructKind, DLUpdateKind, FuncMatch};
+use crate::metadata::{hash_source, InstrumentMeta, SiteKind};
+use crate::preproc::{crosses_directive, enclosing_macro, region_of, Defines, Region};
 use crate::traverse::{
-    get_children_of_kind, get_fn_identifier, get_ident_from_call, get_parent_of_kind,
-    get_var_name_from_assign, get_var_name_from_decl,
+    get_argument, get_arguments, get_assign_stmt_of, get_bounded_parent_of_kind,
+    get_children_of_kind, get_children_of_kind_in_scope, get_decl_initialized_by,
+    get_fn_declarator, get_fn_qualified_name, get_ident_from_call, get_inst_list_insertion,
+    get_outermost_cast,
 };
+use crate::types::{address_op, names_structured_binding, position_op, reference_op, type_of};
+
+/// Scopes nested in a function, whose returns leave themselves rather than the function
+const NESTED_SCOPES: [&str; 3] = [
+    "lambda_expression",
+    "function_definition",
+    "field_declaration_list",
+];
+
+/// The operator taking the object of a member access as a pointer
+fn receiver_op(callee: &Node, code: &str) -> &'static str {
+    let receiver = callee.child_by_field_name("argument").unwrap();
+    let untyped = match callee.child_by_field_name("operator") {
+        Some(op) if op.kind() == "." => "&",
+        _ => "",
+    };
+    address_op(&receiver, untyped, code)
+}
+
+/// The object of a member access as named in the metadata, e.g., `&I` of `I.clone()`
+fn receiver_name(callee: &Node, code: &str) -> String {
+    let receiver = callee.child_by_field_name("argument").unwrap();
+    format!("{}{}", receiver_op(callee, code), receiver.to_source(code))
+}
+
+/// Whether the object of a member access is declared as a non-instruction
+fn is_non_instruction_receiver(callee: &Node, code: &str) -> bool {
+    let receiver = callee.child_by_field_name("argument").unwrap();
+    type_of(&receiver, code).is_some_and(|ty| !ty.is_instruction())
+}
+
+/// Report a site that cannot be hooked without guessing, or is hooked with doubts
+fn report_site(node: &Node, reason: &str, code: &str) {
+    println!(
+        "{}{} {}:\n\t{} {}",
+        "warning".yellow().bold(),
+        format!(": {}", reason).bold(),
+        node.row(),
+        "->".blue().bold(),
+        node.to_source(code),
+    );
+}
+
+/// The source text of an expression as the content of a C++ string literal
+fn name_literal(expr: &str) -> String {
+    expr.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+/// The opening, the closing and the end of the block or the lambda wrapping a hooked call
+fn replacement_scope(call: &Node) -> (&'static str, &'static str, usize) {
+    match call
+        .parent()
+        .filter(|parent| parent.kind() == "expression_statement")
+    {
+        Some(parent_stmt) => ("{ ", " }", parent_stmt.end_byte()),
+        None => ("[&] { ", " }()", call.end_byte()),
+    }
+}
+
+/// Whether a call is wrapped into a lambda by [`replacement_scope`]
+fn is_wrapped_in_lambda(call: &Node) -> bool {
+    call.parent()
+        .is_none_or(|parent| parent.kind() != "expression_statement")
+}
+
+/// Whether the site names a structured binding, which a C++17 lambda cannot capture
+fn names_uncapturable(site: &Node, code: &str) -> bool {
+    let names = names_structured_binding(site, code);
+    if names {
+        report_site(
+            site,
+            "Skip a site naming a structured binding, which its wrapping lambda cannot capture, at line",
+            code,
+        );
+    }
+    names
+}
 
 pub struct Instrumenter {
     parser: Parser,
@@ -17,11 +102,18 @@ pub struct Instrumenter {
     edits: Vec<Edit>,
     edit_track: HashSet<String>,
 
+    /* Hygienic temporaries of the emitted wrappers, keyed by (node range, slot) */
+    tmp_vars: HashMap<(usize, usize, usize), String>,
+    tmp_count: HashMap<usize, usize>,
+
     instr_file_name: String,
+    meta: InstrumentMeta,
+    /// The defines of the build, to tell the sites it compiles
+    defines: Option<Defines>,
 }
 
-impl<'tree> Instrumenter {
-    pub fn new(instr_file_name: String) -> Self {
+impl Instrumenter {
+    pub fn new(instr_file_name: String, defines: Option<Defines>) -> Self {
         let mut parser = Parser::new();
         let grammar = tree_sitter_cpp::language();
         parser
@@ -32,10 +124,19 @@ impl<'tree> Instrumenter {
             parser,
             edits: vec![],
             edit_track: HashSet::new(),
+            tmp_vars: HashMap::new(),
+            tmp_count: HashMap::new(),
+            meta: InstrumentMeta::new(instr_file_name.clone()),
             instr_file_name,
+            defines,
         }
     }
 
+    /// The sites hooked by the instrumentation
+    pub fn meta(&self) -> &InstrumentMeta {
+        &self.meta
+    }
+
     fn add_insert(&mut self, insert_str: String, insert_pos: usize) {
         let edit_hash = insert_pos.to_string() + &insert_str;
         if !self.edit_track.contains(&edit_hash) {
@@ -53,16 +154,153 @@ impl<'tree> Instrumenter {
         }
     }
 
+    /// The temporaries holding the operands of a call, see [`Self::hook_hoisted`]
+    fn operand_tmps(&mut self, call: &Node, count: usize) -> Vec<String> {
+        (0..count).map(|slot| self.tmp_var(call, slot)).collect()
+    }
+
+    /// Run a hook before a call whose operands are evaluated once into temporaries
+    fn hook_hoisted(
+        &mut self,
+        call: &Node,
+        operands: &[(Node, &str)],
+        tmps: &[String],
+        hook_str: &str,
+        code: &str,
+    ) {
+        let (open, close, end_pos) = replacement_scope(call);
+
+        // The call on the temporaries, e.g., `T0->moveBefore(T1)`
+        let mut hoisted_call = String::new();
+        let mut cursor = call.start_byte();
+        for ((operand, _), tmp) in operands.iter().zip(tmps) {
+            hoisted_call.push_str(&code[cursor..operand.start_byte()]);
+            hoisted_call.push_str(tmp);
+            cursor = operand.end_byte();
+        }
+        hoisted_call.push_str(&code[cursor..call.end_byte()]);
+
+        // Keep the operands in place, which preserves their evaluation order and
+        // the edits inside them (e.g., a nested creation):
+        //   I->moveBefore(J)  =>  { auto &&T0 = I; auto &&T1 = J; <hook> T0->moveBefore(T1); }
+        // In an expression position, the lambda returns the value of the call:
+        //   [&] { ..; <hook> return T0->insertInto(T1, T2); }()
+        let insert_str = format!("{}auto &&{} = ", open, tmps[0]);
+        self.add_insert(insert_str, call.start_byte());
+        for (i, tmp) in tmps.iter().enumerate().skip(1) {
+            let replace_str = format!("; auto &&{} = ", tmp);
+            self.add_replace(
+                replace_str,
+                operands[i - 1].0.end_byte(),
+                operands[i].0.start_byte(),
+            );
+        }
+
+        let call_str = if is_wrapped_in_lambda(call) {
+            format!("return {}", hoisted_call)
+        } else {
+            hoisted_call
+        };
+        let replace_str = format!("; {} {};{}", hook_str, call_str, close);
+        self.add_replace(replace_str, operands.last().unwrap().0.end_byte(), end_pos);
+    }
+
+    /// Run a hook after a call returning `void`
+    fn hook_after(&mut self, call: &Node, hook_str: &str) {
+        let (open, close, end_pos) = replacement_scope(call);
+        self.add_insert(open.to_string(), call.start_byte());
+        let insert_str = if is_wrapped_in_lambda(call) {
+            format!("; {}{}", hook_str, close)
+        } else {
+            format!(" {}{}", hook_str, close)
+        };
+        self.add_insert(insert_str, end_pos);
+    }
+
+    /// A unique name (`__disan_tmp_<row>_<n>`) for a temporary of the wrapper around `node`
+    fn tmp_var(&mut self, node: &Node, slot: usize) -> String {
+        let key = (node.start_byte(), node.end_byte(), slot);
+        if let Some(name) = self.tmp_vars.get(&key) {
+            return name.clone();
+        }
+
+        let count = self.tmp_count.entry(node.row()).or_insert(0);
+        let name = format!("__disan_tmp_{}_{}", node.row(), count);
+        *count += 1;
+        self.tmp_vars.insert(key, name.clone());
+        name
+    }
+
+    /// Whether the build compiles the site outside the debug-only macros
+    fn is_built(&self, site: &Node, code: &str) -> bool {
+        let Some(defines) = &self.defines else {
+            return true;
+        };
+        match region_of(site, code, defines) {
+            Region::Built => true,
+            Region::Undetermined => {
+                report_site(
+                    site,
+                    "Hook a site under a condition undecided by the build at line",
+                    code,
+                );
+                true
+            }
+            Region::NotBuilt => {
+                report_site(
+                    site,
+                    "Skip a site in a branch not taken by the build at line",
+                    code,
+                );
+                false
+            }
+            Region::DebugOnly(macro_name) => {
+                report_site(
+                    site,
+                    &format!("Skip a site in the debug-only `{}` at line", macro_name),
+                    code,
+                );
+                false
+            }
+        }
+    }
+
+    /// Whether the site is built and its hooks land outside macro arguments and directives
+    fn is_hookable(&self, site: &Node, code: &str) -> bool {
+        if !self.is_built(site, code) {
+            return false;
+        }
+        if let Some(macro_name) = enclosing_macro(site, code) {
+            report_site(
+                site,
+                &format!(
+                    "Skip a site in the arguments of the macro `{}` at line",
+                    macro_name
+                ),
+                code,
+            );
+            return false;
+        }
+        if crosses_directive(site) {
+            report_site(
+                site,
+                "Skip a site in a statement split by preprocessor directives at line",
+                code,
+            );
+            return false;
+        }
+        true
+    }
+
     fn collect_init_and_clean_up_edit(&mut self, pass_entry: &Node, code: &str) {
         /* Check the parameter list */
-        let param_list = pass_entry
-            .child_by_field_name("declarator")
+        let param_list = get_fn_declarator(pass_entry)
             .unwrap()
             .child_by_field_name("parameters")
             .unwrap();
         let params = get_children_of_kind(&param_list, "parameter_declaration");
         assert!(
-            params.len() >= 1,
+            !params.is_empty(),
             "The pass entry should have the target parameters!"
         );
 
@@ -105,42 +343,55 @@ impl<'tree> Instrumenter {
         );
         self.add_insert(init_str, fn_body.child(1).unwrap().start_byte());
 
-        let return_stmts = get_children_of_kind(&fn_body, "return_statement");
+        /* The returns of the lambdas and the local classes do not leave the pass */
+        let return_stmts =
+            get_children_of_kind_in_scope(&fn_body, "return_statement", &NESTED_SCOPES);
         for return_stmt in return_stmts {
-            let insert_str = format!("{{ RC->startCheck(); delete RC; ");
-            self.add_insert(insert_str, return_stmt.start_byte());
-            let insert_str = format!(" }}");
-            self.add_insert(insert_str, return_stmt.end_byte());
+            let value = return_stmt
+                .named_child(0)
+                .filter(|value| !matches!(value.kind(), "comment" | "initializer_list"));
+            match value {
+                /* Evaluate the value before the check, since it may run hooked lambdas:
+                 *   return F(..);  =>  { auto T = F(..); RC->startCheck(); delete RC; return T; }
+                 */
+                Some(value) => {
+                    let tmp = self.tmp_var(&return_stmt, 0);
+                    let replace_str = format!("{{ auto {} = ", tmp);
+                    self.add_replace(replace_str, return_stmt.start_byte(), value.start_byte());
+                    let insert_str = format!(" RC->startCheck(); delete RC; return {}; }}", tmp);
+                    self.add_insert(insert_str, return_stmt.end_byte());
+                }
+                None => {
+                    let insert_str = "{ RC->startCheck(); delete RC; ".to_string();
+                    self.add_insert(insert_str, return_stmt.start_byte());
+                    let insert_str = " }".to_string();
+                    self.add_insert(insert_str, return_stmt.end_byte());
+                }
+            }
         }
     }
 
     /// The main function to perform AST-level instrumentation
     pub fn instrument(&mut self, buf: &mut String) {
         // self.collect_edits(buf);
+        self.meta.source_hash = hash_source(buf);
         self.visit_ast_tree(buf);
 
-        self.edits.sort_by(|a, b| b.start_pos.cmp(&a.start_pos));
-        for edit in &self.edits {
-            match edit.kind {
-                EditKind::Insert => {
-                    buf.insert_str(edit.start_pos, &edit.content);
-                }
-                EditKind::Replace(end_pos) => {
-                    buf.replace_range(edit.start_pos..end_pos, &edit.content);
-                }
-            }
-        }
+        *buf = apply_edits(buf, &self.edits);
     }
 }
 
 impl Instrumenter {
     fn visit_header_includes(&mut self, nodes: Vec<Node>) {
-        assert_eq!(nodes.is_empty(), false, "No header includes in the code!");
-        self.add_insert(Hook::header_include().to_string(), nodes[0].start_byte());
+        assert!(!nodes.is_empty(), "No header includes in the code!");
+        self.add_insert(
+            Hook::header_include().to_string() + &Hook::abi_check(),
+            nodes[0].start_byte(),
+        );
     }
 
     fn visit_using_decls(&mut self, nodes: Vec<Node>) {
-        assert_eq!(nodes.is_empty(), false, "No using declaration in the code!");
+        assert!(!nodes.is_empty(), "No using declaration in the code!");
         self.add_insert(Hook::global_var_decl().to_string(), nodes[0].end_byte() + 1);
     }
 
@@ -152,50 +403,166 @@ impl Instrumenter {
         if callee.kind() != ASTNodeKind::FieldExpr.to_string() {
             return;
         }
-
-        let inserted_inst = callee.child_by_field_name("argument").unwrap();
-        let insert_pos = match callee_name {
-            // 1 - void Instruction::insertBefore(BasicBlock::iterator InsertPos);
-            // 2 - void Instruction::insertBefore(BasicBlock &BB, InstListType::iterator InsertPos);
-            "insertBefore" => {
-                match arguments.child_count() {
-                    3 => {
-                        // 1
-                        let insert_it = arguments.child(1).unwrap();
-                        format!("&*{}", insert_it.to_source(code))
+        let args = get_arguments(&arguments);
+
+        // The operands, taken by the operators picking the overload of the hook,
+        // and the index of the inserted instruction among them
+        let (inserted, operands) = match callee_name {
+            // iplist::iterator iplist::insert(iterator where, Instruction *New);
+            //     of BB->getInstList(), arg 0: the insert position, arg 1: the inserted instruction
+            "insert" => {
+                let Some((getter, inserted_inst)) = get_inst_list_insertion(&callee, &args, code)
+                else {
+                    return;
+                };
+                if !self.is_hookable(&call, code) {
+                    return;
+                }
+                let block = getter.child_by_field_name("argument").unwrap();
+                let is_pointer = getter.child_by_field_name("operator").unwrap().kind() == "->";
+                let block_op = reference_op(&block, is_pointer, code);
+                (
+                    2,
+                    vec![
+                        (block, block_op),
+                        (args[0], ""),
+                        (inserted_inst, address_op(&inserted_inst, "", code)),
+                    ],
+                )
+            }
+            _ if callee_name.is_insertion() => {
+                let inserted_inst = callee.child_by_field_name("argument").unwrap();
+                if is_non_instruction_receiver(&callee, code) {
+                    report_site(
+                        &call,
+                        "Skip an insertion of a non-instruction at line",
+                        code,
+                    );
+                    return;
+                }
+                let inserted = (inserted_inst, receiver_op(&callee, code));
+                match (callee_name, args.len()) {
+                    // 1 - void Instruction::insertBefore(InstListType::iterator InsertPos);
+                    //     arg 0: the insert position, or the instruction of the older overload
+                    // 2 - void Instruction::insertAfter(Instruction *InsertPos);
+                    //     arg 0: the insert position
+                    ("insertBefore" | "insertAfter", 1) => {
+                        (0, vec![inserted, (args[0], position_op(&args[0], code))])
                     }
-                    5 => {
-                        // 2
-                        let insert_bb = arguments.child(3).unwrap();
-                        format!("&{}", insert_bb.to_source(code))
+                    // 3 - void Instruction::insertBefore(BasicBlock &BB, InstListType::iterator InsertPos);
+                    //     arg 0: the parent block, arg 1: the insert position
+                    ("insertBefore", 2) => (
+                        0,
+                        vec![
+                            inserted,
+                            (args[0], reference_op(&args[0], false, code)),
+                            (args[1], ""),
+                        ],
+                    ),
+                    // 4 - InstListType::iterator Instruction::insertInto(BasicBlock *ParentBB,
+                    //                                                    InstListType::iterator It);
+                    //     arg 0: the parent block, arg 1: the insert position
+                    ("insertInto", 2) => (
+                        0,
+                        vec![
+                            inserted,
+                            (args[0], reference_op(&args[0], true, code)),
+                            (args[1], ""),
+                        ],
+                    ),
+                    _ => {
+                        return;
                     }
-                    _ => unreachable!(),
                 }
             }
-            // void Instruction::insertAfter(Instruction *InsertPos);
-            "insertAfter" => arguments.child(1).unwrap().to_source(code),
-            // BasicBlock::iterator Instruction::insertInto(BasicBlock *ParentBB, BasicBlock::iterator It);
-            "insertInto" => arguments.child(1).unwrap().to_source(code),
             _ => {
                 return;
             }
         };
 
-        let field_op = callee.child(1).unwrap().to_source(code);
+        if is_wrapped_in_lambda(&call) && names_uncapturable(&call, code) {
+            return;
+        }
+        let inserted_inst = operands[inserted].0;
+        let pos_name = if inserted == 0 {
+            args.last()
+        } else {
+            args.first()
+        };
+        let pos_name = pos_name.unwrap().to_source(code);
+        let tmps = self.operand_tmps(&call, operands.len());
+        let position = (0..operands.len())
+            .filter(|&i| i != inserted)
+            .map(|i| format!("{}{}", operands[i].1, tmps[i]))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let hook_str = format!(
+            "RC->trackInsertion({}{}, {}, {}, \"{}\", \"{}\");",
+            operands[inserted].1,
+            tmps[inserted],
+            position,
+            call.row(),
+            name_literal(&inserted_inst.to_source(code)),
+            name_literal(&pos_name),
+        );
+        self.hook_hoisted(&call, &operands, &tmps, &hook_str, code);
 
-        let insert_str = format!(
-            "{{ RC->trackInsertion({}{}, {}, {}, \"{}\", \"{}\"); ",
-            if field_op.as_str() == "." { "&" } else { "" },
-            inserted_inst.to_source(code),
-            insert_pos,
+        self.meta.add_site(
             call.row(),
-            inserted_inst.to_source(code),
-            insert_pos,
+            SiteKind::Insert,
+            format!("{}{}", operands[inserted].1, inserted_inst.to_source(code)),
+            vec![pos_name],
         );
-        self.add_insert(insert_str, call.start_byte());
+    }
+
+    /// Wrap a creation in an expression position into an immediately-evaluated lambda
+    fn wrap_creation(&mut self, creation: &Node, code: &str) {
+        if names_uncapturable(creation, code) {
+            return;
+        }
+        let tmp = self.tmp_var(creation, 0);
+
+        let insert_str = format!("[&] {{ auto *{} = ", tmp);
+        self.add_insert(insert_str, creation.start_byte());
+
+        let insert_str = format!(
+            "; RC->trackDebugLocDst({}, nullptr, {}, {}, \"{}\", \"\"); return {}; }}()",
+            tmp,
+            ConstructKind::Creating,
+            creation.row(),
+            name_literal(&creation.to_source(code)),
+            tmp,
+        );
+        self.add_insert(insert_str, creation.end_byte());
+
+        self.meta
+            .add_site(creation.row(), SiteKind::Construct, String::new(), vec![]);
+    }
+
+    /// Hoist the created value of `return value;` into a tracked temporary
+    fn hoist_return_creation(&mut self, parent_return: &Node, creation: &Node, code: &str) {
+        let tmp = self.tmp_var(creation, 0);
+
+        // Keep the returned value in place so that the edits inside it are not overwritten
+        let replace_str = format!("{{ auto *{} = ", tmp);
+        self.add_replace(
+            replace_str,
+            parent_return.start_byte(),
+            creation.start_byte(),
+        );
+
+        let replace_str = format!(
+            "; RC->trackDebugLocDst({}, nullptr, {}, {}, \"{}\", \"\"); return {}; }}",
+            tmp,
+            ConstructKind::Creating,
+            creation.row(),
+            name_literal(&creation.to_source(code)),
+            tmp,
+        );
+        self.add_replace(replace_str, creation.end_byte(), parent_return.end_byte());
 
-        let insert_str = format!(" }}");
-        self.add_insert(insert_str, call.end_byte() + 1);
+        self.meta
+            .add_site(creation.row(), SiteKind::Construct, String::new(), vec![]);
     }
 
     fn visit_fn_calls(&mut self, nodes: Vec<Node>, code: &str) {
@@ -212,100 +579,121 @@ impl Instrumenter {
                 "qualified_identifier" => {}
                 _ => continue,
             };
+            if callee_name.is_hooked() && !self.is_hookable(&call, code) {
+                continue;
+            }
 
             match callee_name.is_construct() {
                 Some(ConstructKind::Creating) => {
-                    if let Some(parent_decl) = get_parent_of_kind(&call, "declaration") {
-                        let var_name = get_var_name_from_decl(&parent_decl);
+                    if let Some((parent_decl, var_name)) = get_decl_initialized_by(&call) {
                         let insert_str = format!(
                             " RC->trackDebugLocDst({}, nullptr, {}, {}, \"{}\", \"\");",
                             var_name.to_source(code),
                             ConstructKind::Creating,
                             parent_decl.row(),
-                            var_name.to_source(code),
+                            name_literal(&var_name.to_source(code)),
                         );
                         self.add_insert(insert_str, parent_decl.end_byte());
+
+                        self.meta.add_site(
+                            parent_decl.row(),
+                            SiteKind::Construct,
+                            var_name.to_source(code),
+                            vec![],
+                        );
                         continue;
                     }
 
-                    if let Some(parent_assign) = get_parent_of_kind(&call, "assignment_expression")
-                    {
-                        let var_name = get_var_name_from_assign(&parent_assign);
-
-                        let insert_str = format!("{{ ");
-                        self.add_insert(insert_str, parent_assign.start_byte());
+                    if let Some((parent_stmt, var_name)) = get_assign_stmt_of(&call) {
+                        let insert_str = "{ ".to_string();
+                        self.add_insert(insert_str, parent_stmt.start_byte());
 
                         let insert_str = format!(
                             " RC->trackDebugLocDst({}, nullptr, {}, {}, \"{}\", \"\"); }}",
                             var_name.to_source(code),
                             ConstructKind::Creating,
-                            parent_assign.row(),
+                            parent_stmt.row(),
+                            name_literal(&var_name.to_source(code)),
+                        );
+                        self.add_insert(insert_str, parent_stmt.end_byte());
+
+                        self.meta.add_site(
+                            parent_stmt.row(),
+                            SiteKind::Construct,
                             var_name.to_source(code),
+                            vec![],
                         );
-                        self.add_insert(insert_str, parent_assign.end_byte() + 1);
                         continue;
                     }
 
-                    if let Some(parent_return) = get_parent_of_kind(&call, "return_statement") {
-                        let replace_str = format!(
-                            "{{ auto *V = {}; RC->trackDebugLocDst(V, nullptr, {}, {}, \"\", \"\"); return V; }}", 
-                            call.to_source(code),
+                    if let Some(parent_return) =
+                        call.parent().filter(|p| p.kind() == "return_statement")
+                    {
+                        self.hoist_return_creation(&parent_return, &call, code);
+                        continue;
+                    }
+
+                    /* BranchInst::Create(..); */
+                    if let Some(parent_stmt) =
+                        call.parent().filter(|p| p.kind() == "expression_statement")
+                    {
+                        let tmp = self.tmp_var(&call, 0);
+
+                        let insert_str = format!("{{ auto *{} = ", tmp);
+                        self.add_insert(insert_str, parent_stmt.start_byte());
+
+                        let insert_str = format!(
+                            " RC->trackDebugLocDst({}, nullptr, {}, {}, \"{}\", \"\"); }}",
+                            tmp,
                             ConstructKind::Creating,
                             call.row(),
+                            name_literal(&call.to_source(code)),
                         );
+                        self.add_insert(insert_str, parent_stmt.end_byte());
 
-                        self.add_replace(
-                            replace_str,
-                            parent_return.start_byte(),
-                            parent_return.end_byte(),
-                        );
+                        self.meta
+                            .add_site(call.row(), SiteKind::Construct, String::new(), vec![]);
                         continue;
                     }
 
-                    if let Some(parent) = call.parent() {
-                        if parent.kind() == "expression_statement" {
-                            let replace_str = format!(
-                                "Instruction *I = {}; RC->trackDebugLocDst(I, nullptr, {}, {}, \"\", \"\")",
-                                call.to_source(code),
-                                ConstructKind::Creating,
-                                call.row(),
-                            );
-                            self.add_replace(replace_str, call.start_byte(), call.end_byte());
-                        }
-                    }
+                    /* Worklist.push(CastInst::Create(..)), Cond ? PHINode::Create(..) : nullptr, ... */
+                    self.wrap_creation(&call, code);
                 }
                 /* auto *NI = OI->clone(); */
                 Some(ConstructKind::Cloning) => {
                     let original_inst = callee.child_by_field_name("argument").unwrap();
-                    let addr_op = if callee.child(1).unwrap().to_source(code).as_str() == "->" {
-                        ""
-                    } else {
-                        "&"
-                    };
-                    if let Some(parent_decl) = get_parent_of_kind(&call, "declaration") {
-                        let var_name = get_var_name_from_decl(&parent_decl);
-
+                    if is_non_instruction_receiver(&callee, code) {
+                        report_site(&call, "Skip a clone of a non-instruction at line", code);
+                        continue;
+                    }
+                    let addr_op = receiver_op(&callee, code);
+                    // The clone may be stored through casts, e.g., `cast<Instruction>(OI->clone())`
+                    let stored = get_outermost_cast(&call, code);
+                    if let Some((parent_decl, var_name)) = get_decl_initialized_by(&stored) {
                         let insert_str = format!(
                             " RC->trackDebugLocDst({}, {}{}, {}, {}, \"{}\", \"{}\");",
-                            var_name.to_source(&code),
+                            var_name.to_source(code),
                             addr_op,
                             original_inst.to_source(code),
                             ConstructKind::Cloning,
                             parent_decl.row(),
-                            var_name.to_source(&code),
-                            original_inst.to_source(code),
+                            name_literal(&var_name.to_source(code)),
+                            name_literal(&original_inst.to_source(code)),
                         );
                         self.add_insert(insert_str, parent_decl.end_byte());
 
+                        self.meta.add_site(
+                            parent_decl.row(),
+                            SiteKind::Construct,
+                            var_name.to_source(code),
+                            vec![receiver_name(&callee, code)],
+                        );
                         continue;
                     }
 
-                    if let Some(parent_assign) = get_parent_of_kind(&call, "assignment_expression")
-                    {
-                        let var_name = get_var_name_from_assign(&parent_assign);
-
-                        let insert_str = format!("{{ ");
-                        self.add_insert(insert_str, parent_assign.start_byte());
+                    if let Some((parent_stmt, var_name)) = get_assign_stmt_of(&stored) {
+                        let insert_str = "{ ".to_string();
+                        self.add_insert(insert_str, parent_stmt.start_byte());
 
                         let insert_str = format!(
                             " RC->trackDebugLocDst({}, {}{}, {}, {}, \"{}\", \"{}\"); }}",
@@ -313,50 +701,89 @@ impl Instrumenter {
                             addr_op,
                             original_inst.to_source(code),
                             ConstructKind::Cloning,
-                            parent_assign.row(),
-                            var_name.to_source(code),
-                            original_inst.to_source(code),
+                            parent_stmt.row(),
+                            name_literal(&var_name.to_source(code)),
+                            name_literal(&original_inst.to_source(code)),
                         );
-                        self.add_insert(insert_str, parent_assign.end_byte() + 1);
+                        self.add_insert(insert_str, parent_stmt.end_byte());
 
+                        self.meta.add_site(
+                            parent_stmt.row(),
+                            SiteKind::Construct,
+                            var_name.to_source(code),
+                            vec![receiver_name(&callee, code)],
+                        );
                         continue;
                     }
 
-                    call.dump_source(code);
-                    panic!("Failed to parse instruction clone!");
+                    /* auto *NI = OI->clone()->getNextNode(); */
+                    let reason = if get_bounded_parent_of_kind(&call, "declaration").is_some()
+                        || get_bounded_parent_of_kind(&call, "assignment_expression").is_some()
+                    {
+                        "Encounter an instruction clone not directly stored at line"
+                    } else {
+                        "Encounter an instruction clone without destination at line"
+                    };
+                    report_site(&call, reason, code);
                 }
                 /* I->moveBefore(D, ..); */
                 Some(ConstructKind::Moving) => {
+                    // 1 - void Instruction::moveBefore(Instruction *MovePos);
+                    //     arg 0: the move position
+                    // 2 - void Instruction::moveBefore(InstListType::iterator MovePos);
+                    //     arg 0: the move position, which may be an end()
+                    // 3 - void Instruction::moveBefore(BasicBlock &BB, InstListType::iterator I);
+                    //     arg 0: the destination block (by reference), arg 1: the move position
                     let debugloc_dst = callee.child_by_field_name("argument").unwrap();
-                    let move_dst = match arguments.child_count() {
-                        3 => arguments.child(1).unwrap().to_source(code),
-                        5 => {
-                            format!("&{}", arguments.child(1).unwrap().to_source(code))
-                        }
+                    if is_non_instruction_receiver(&callee, code) {
+                        report_site(&call, "Skip a move of a non-instruction at line", code);
+                        continue;
+                    }
+                    let args = get_arguments(&arguments);
+                    let moved = (debugloc_dst, receiver_op(&callee, code));
+                    let operands = match args.len() {
+                        1 => vec![moved, (args[0], position_op(&args[0], code))],
+                        2 => vec![
+                            moved,
+                            (args[0], reference_op(&args[0], false, code)),
+                            (args[1], ""),
+                        ],
                         _ => unreachable!(),
                     };
-                    let field_op = callee.child(1).unwrap().to_source(code);
-                    let ref_op = if field_op.as_str() == "->" { "" } else { "&" };
+                    let move_dst_name = args.last().unwrap().to_source(code);
+                    if is_wrapped_in_lambda(&call) && names_uncapturable(&call, code) {
+                        continue;
+                    }
 
-                    let insert_str = format!(
-                        "{{ RC->trackDebugLocDst({}{}, {}, {}, {}, \"{}\", \"{}\"); ",
-                        ref_op,
-                        debugloc_dst.to_source(code),
+                    let tmps = self.operand_tmps(&call, operands.len());
+                    let move_dst = (1..operands.len())
+                        .map(|i| format!("{}{}", operands[i].1, tmps[i]))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let hook_str = format!(
+                        "RC->trackDebugLocDst({}{}, {}, {}, {}, \"{}\", \"{}\");",
+                        operands[0].1,
+                        tmps[0],
                         move_dst,
                         ConstructKind::Moving,
                         call.row(),
-                        debugloc_dst.to_source(code),
-                        move_dst,
+                        name_literal(&debugloc_dst.to_source(code)),
+                        name_literal(&move_dst_name),
                     );
-                    self.add_insert(insert_str, call.start_byte());
+                    self.hook_hoisted(&call, &operands, &tmps, &hook_str, code);
 
-                    let insert_str = format!(" }}");
-                    self.add_insert(insert_str, call.end_byte() + 1);
+                    self.meta.add_site(
+                        call.row(),
+                        SiteKind::Construct,
+                        receiver_name(&callee, code),
+                        vec![],
+                    );
                 }
-                None => {}
+                Some(ConstructKind::Untracked) | None => {}
             };
 
             match callee_name.as_str() {
+                _ if !callee_name.is_replacement() => {}
                 /* OldI->replaceAllUsesWith(NewI) */
                 "replaceAllUsesWith" => {
                     /* The S-expr of `DLS->replaceAllUsesWith(DLD)` is shown as following:
@@ -372,138 +799,215 @@ impl Instrumenter {
                      */
                     let debugloc_src = callee.child_by_field_name("argument").unwrap();
                     let debugloc_src_str = debugloc_src.to_source(code);
-                    let debugloc_dst = arguments.child(1).unwrap(); // Only one arg
+                    // arg 0: the new value
+                    let debugloc_dst = get_argument(&arguments, 0).unwrap();
                     let debugloc_dst_str = debugloc_dst.to_source(code);
 
+                    if is_wrapped_in_lambda(&call) && names_uncapturable(&call, code) {
+                        continue;
+                    }
                     // We need to distinguish between `Value &` (DLS.replace) and `Value *` (DLS->replace)
-                    let field_operator = callee.child(1).unwrap().to_source(code);
-                    let prepare_str = format!(
-                        "Value *DebugLocSrc = {}{}; Value *DebugLocDst = {};",
-                        if field_operator.as_str() == "." {
-                            "&"
-                        } else {
-                            ""
-                        },
-                        debugloc_src_str,
-                        debugloc_dst_str,
+                    let addr_op = receiver_op(&callee, code);
+                    let (tmp_src, tmp_dst) = (self.tmp_var(&call, 0), self.tmp_var(&call, 1));
+                    let (open, close, end_pos) = replacement_scope(&call);
+
+                    let hook_str = format!(
+                        "RC->trackDebugLocSrc({}, {}, {}, \"{}\", \"{}\");",
+                        tmp_dst,
+                        tmp_src,
+                        call.row(),
+                        name_literal(&debugloc_dst_str),
+                        name_literal(&debugloc_src_str),
                     );
 
-                    let replace_str = format!("DebugLocSrc->replaceAllUsesWith(DebugLocDst);",);
+                    // Keep the operands in place, which preserves their evaluation order and
+                    // the edits inside them (e.g., a nested creation):
+                    //   OldI->replaceAllUsesWith(NewI)
+                    //     => { Value *S = OldI; Value *D = NewI; S->replaceAllUsesWith(D); <hook> }
+                    let insert_str = format!("{}Value *{} = {}", open, tmp_src, addr_op,);
+                    self.add_insert(insert_str, call.start_byte());
 
-                    let hook_str = format!(
-                        "RC->trackDebugLocSrc(DebugLocDst, DebugLocSrc, {}, \"{}\", \"{}\");",
+                    let replace_str = format!("; Value *{} = ", tmp_dst);
+                    self.add_replace(
+                        replace_str,
+                        debugloc_src.end_byte(),
+                        debugloc_dst.start_byte(),
+                    );
+
+                    let replace_str = format!(
+                        "; {}->replaceAllUsesWith({}); {}{}",
+                        tmp_src, tmp_dst, hook_str, close
+                    );
+                    self.add_replace(replace_str, debugloc_dst.end_byte(), end_pos);
+
+                    self.meta.add_site(
                         call.row(),
+                        SiteKind::Replace,
                         debugloc_dst_str,
-                        debugloc_src_str,
+                        vec![receiver_name(&callee, code)],
                     );
-
-                    let replace_str = format!("{{ {} {} {} }}", prepare_str, replace_str, hook_str);
-                    self.add_replace(replace_str, call.start_byte(), call.end_byte() + 1);
                 }
                 /* I->replaceUsesOfWith(OldI, NewI); */
                 "replaceUsesOfWith" => {
-                    if call.parent().unwrap().kind() != "expression_statement" {
-                        panic!("{}", "Non expression statement parent!".red().bold());
+                    let called_obj = callee.child_by_field_name("argument").unwrap();
+                    // arg 0: the old value, arg 1: the new value
+                    let old_inst = get_argument(&arguments, 0).unwrap();
+                    let new_inst = get_argument(&arguments, 1).unwrap();
+                    if is_wrapped_in_lambda(&call) && names_uncapturable(&call, code) {
+                        continue;
                     }
 
-                    let called_obj = callee.child_by_field_name("argument").unwrap();
-                    let old_inst = arguments.child(1).unwrap();
-                    let new_inst = arguments.child(3).unwrap();
+                    let addr_op = receiver_op(&callee, code);
+                    let (tmp_src, tmp_dst) = (self.tmp_var(&call, 0), self.tmp_var(&call, 1));
+                    let tmp_user = self.tmp_var(&call, 2);
+                    let (open, close, end_pos) = replacement_scope(&call);
 
-                    let field_operator = callee.child(1).unwrap().to_source(code);
-                    let prepare_str = format!(
-                        "Value *DebugLocSrc = {}; Value *DebugLocDst = {};",
-                        old_inst.to_source(code),
-                        new_inst.to_source(code),
+                    let hook_str = format!(
+                        "RC->trackDebugLocSrc({}, {}, {}, \"{}\", \"{}\");",
+                        tmp_dst,
+                        tmp_src,
+                        call.row(),
+                        name_literal(&new_inst.to_source(code)),
+                        name_literal(&old_inst.to_source(code)),
                     );
 
-                    let inst_repl_str = format!(
-                        "{}{}replaceUsesOfWith(DebugLocSrc, DebugLocDst);",
-                        called_obj.to_source(code),
-                        field_operator,
-                    );
+                    //   U->replaceUsesOfWith(OldI, NewI)
+                    //     => { auto *T = U; Value *S = OldI; Value *D = NewI;
+                    //          T->replaceUsesOfWith(S, D); <hook> }
+                    // In an expression position, the lambda returns the value of the call,
+                    // which is `void` in the older LLVMs, and hooks it on the way out:
+                    //     => [&] { ..; auto G = make_scope_exit([&] { <hook> });
+                    //              return T->replaceUsesOfWith(S, D); }()
+                    let insert_str = format!("{}auto *{} = {}", open, tmp_user, addr_op,);
+                    self.add_insert(insert_str, call.start_byte());
 
-                    let hook_str = format!(
-                        "RC->trackDebugLocSrc(DebugLocDst, DebugLocSrc, {}, \"{}\", \"{}\");",
+                    let replace_str = format!("; Value *{} = ", tmp_src);
+                    self.add_replace(replace_str, called_obj.end_byte(), old_inst.start_byte());
+
+                    let replace_str = format!("; Value *{} = ", tmp_dst);
+                    self.add_replace(replace_str, old_inst.end_byte(), new_inst.start_byte());
+
+                    let replace_str = if is_wrapped_in_lambda(&call) {
+                        let tmp_guard = self.tmp_var(&call, 3);
+                        format!(
+                            "; auto {} = llvm::make_scope_exit([&] {{ {} }}); return {}->replaceUsesOfWith({}, {});{}",
+                            tmp_guard, hook_str, tmp_user, tmp_src, tmp_dst, close
+                        )
+                    } else {
+                        format!(
+                            "; {}->replaceUsesOfWith({}, {}); {}{}",
+                            tmp_user, tmp_src, tmp_dst, hook_str, close
+                        )
+                    };
+                    self.add_replace(replace_str, new_inst.end_byte(), end_pos);
+
+                    self.meta.add_site(
                         call.row(),
-                        old_inst.to_source(code),
+                        SiteKind::Replace,
                         new_inst.to_source(code),
+                        vec![old_inst.to_source(code)],
                     );
-
-                    let replace_str =
-                        format!("{{ {} {} {} }}", prepare_str, inst_repl_str, hook_str);
-                    self.add_replace(replace_str, call.start_byte(), call.end_byte() + 1);
                 }
                 _ => {}
             };
 
-            match callee_name.is_debugloc_update() {
+            let update = callee_name
+                .is_debugloc_update()
+                .filter(|_| !(is_wrapped_in_lambda(&call) && names_uncapturable(&call, code)));
+            match update {
                 Some(DLUpdateKind::Preserving) => {
                     let debugloc_dst = callee.child_by_field_name("argument").unwrap();
-                    let debugloc = arguments.child(1).unwrap();
+                    // arg 0: the preserved debug location
+                    let debugloc = get_argument(&arguments, 0).unwrap();
                     let debugloc_src = if debugloc.kind() == "call_expression" {
                         get_ident_from_call(&debugloc, "getDebugLoc", code)
                     } else {
                         None
                     };
 
-                    let insert_str = format!("{{ ");
-                    self.add_insert(insert_str, call.start_byte());
-
-                    let insert_str = format!(
-                        " RC->trackDebugLocPreserving({}, nullptr, {}, \"{}\", \"nullptr\"); }}",
+                    let hook_str = format!(
+                        "RC->trackDebugLocPreserving({}{}, nullptr, {}, \"{}\", \"{}\");",
+                        receiver_op(&callee, code),
                         debugloc_dst.to_source(code),
                         call.row(),
-                        debugloc_dst.to_source(code),
+                        name_literal(&debugloc_dst.to_source(code)),
+                        name_literal(
+                            &debugloc_src.map_or("nullptr".to_string(), |src| src.to_source(code))
+                        ),
                     );
+                    self.hook_after(&call, &hook_str);
 
-                    self.add_insert(insert_str, call.end_byte() + 1);
+                    self.meta.add_site(
+                        call.row(),
+                        SiteKind::Update,
+                        receiver_name(&callee, code),
+                        debugloc_src
+                            .map(|src| receiver_name(&src.parent().unwrap(), code))
+                            .into_iter()
+                            .collect(),
+                    );
                 }
                 Some(DLUpdateKind::Merging) => {
                     let debugloc_dst = callee.child_by_field_name("argument").unwrap();
 
-                    let debugloc_1 = arguments.child(1).unwrap();
+                    // arg 0 and arg 1: the two merged debug locations
+                    let debugloc_1 = get_argument(&arguments, 0).unwrap();
                     let debugloc_src_1 = if debugloc_1.kind() == "call_expression" {
                         get_ident_from_call(&debugloc_1, "getDebugLoc", code)
                     } else {
                         None
                     };
 
-                    let debugloc_2 = arguments.child(3).unwrap();
+                    let debugloc_2 = get_argument(&arguments, 1).unwrap();
                     let debugloc_src_2 = if debugloc_2.kind() == "call_expression" {
                         get_ident_from_call(&debugloc_2, "getDebugLoc", code)
                     } else {
                         None
                     };
 
-                    let insert_str = format!(
-                        " RC->trackDebugLocMerging({}, nullptr, nullptr, {}, \"\", \"\", \"\");",
+                    let hook_str = format!(
+                        "RC->trackDebugLocMerging({}{}, nullptr, nullptr, {}, \"{}\", \"{}\", \"{}\");",
+                        receiver_op(&callee, code),
                         debugloc_dst.to_source(code),
                         debugloc_dst.row(),
+                        name_literal(&debugloc_dst.to_source(code)),
+                        name_literal(&debugloc_src_1.map_or(String::new(), |src| src.to_source(code))),
+                        name_literal(&debugloc_src_2.map_or(String::new(), |src| src.to_source(code))),
+                    );
+                    self.hook_after(&call, &hook_str);
+
+                    self.meta.add_site(
+                        debugloc_dst.row(),
+                        SiteKind::Update,
+                        receiver_name(&callee, code),
+                        [debugloc_src_1, debugloc_src_2]
+                            .iter()
+                            .flatten()
+                            .map(|src| receiver_name(&src.parent().unwrap(), code))
+                            .collect(),
                     );
-                    self.add_insert(insert_str, call.end_byte() + 1);
                 }
                 Some(DLUpdateKind::Dropping) => {
                     let debugloc_dst = callee.child_by_field_name("argument").unwrap();
-                    let addr_op = if callee.child(1).unwrap().to_source(code).as_str() == "->" {
-                        ""
-                    } else {
-                        "&"
-                    };
-
-                    let insert_str = format!("{{ ");
-                    self.add_insert(insert_str, call.start_byte());
+                    let addr_op = receiver_op(&callee, code);
 
-                    let insert_str = format!(
-                        " RC->trackDebugLocDropping({}{}, {}, \"{}\"); }}",
+                    let hook_str = format!(
+                        "RC->trackDebugLocDropping({}{}, {}, \"{}\");",
                         addr_op,
                         debugloc_dst.to_source(code),
                         call.row(),
-                        debugloc_dst.to_source(code),
+                        name_literal(&debugloc_dst.to_source(code)),
+                    );
+                    self.hook_after(&call, &hook_str);
+
+                    self.meta.add_site(
+                        call.row(),
+                        SiteKind::Update,
+                        receiver_name(&callee, code),
+                        vec![],
                     );
-                    self.add_insert(insert_str, call.end_byte() + 1);
                 }
-                None => {}
+                Some(DLUpdateKind::Others) | None => {}
             };
 
             self.try_visit_insertions(call, &callee_name, code);
@@ -514,71 +1018,81 @@ impl Instrumenter {
         for new in nodes {
             let new_type = new.child_by_field_name("type").unwrap();
             let new_type_str = new_type.to_source(code);
-            if let Some(ConstructKind::Creating) = new_type_str.is_construct() {
-                if let Some(parent_decl) = get_parent_of_kind(&new, "declaration") {
-                    let var_name = get_var_name_from_decl(&parent_decl);
+            if new_type_str.is_construct() == Some(ConstructKind::Creating)
+                && self.is_hookable(&new, code)
+            {
+                if let Some((parent_decl, var_name)) = get_decl_initialized_by(&new) {
                     let insert_str = format!(
                         " RC->trackDebugLocDst({}, nullptr, {}, {}, \"{}\", \"\");",
                         var_name.to_source(code),
                         ConstructKind::Creating,
                         new.row(),
-                        var_name.to_source(code),
+                        name_literal(&var_name.to_source(code)),
                     );
 
                     self.add_insert(insert_str, parent_decl.end_byte());
+
+                    self.meta.add_site(
+                        new.row(),
+                        SiteKind::Construct,
+                        var_name.to_source(code),
+                        vec![],
+                    );
                     continue;
                 }
 
-                if let Some(parent_assign) = get_parent_of_kind(&new, "assignment_expression") {
-                    let var_name = get_var_name_from_assign(&parent_assign);
-
-                    let insert_str = format!("{{ ");
-                    self.add_insert(insert_str, parent_assign.start_byte());
+                if let Some((parent_stmt, var_name)) = get_assign_stmt_of(&new) {
+                    let insert_str = "{ ".to_string();
+                    self.add_insert(insert_str, parent_stmt.start_byte());
 
                     let insert_str = format!(
                         " RC->trackDebugLocDst({}, nullptr, {}, {}, \"{}\", \"\"); }}",
                         var_name.to_source(code),
                         ConstructKind::Creating,
                         new.row(),
-                        var_name.to_source(code),
+                        name_literal(&var_name.to_source(code)),
                     );
 
-                    self.add_insert(insert_str, parent_assign.end_byte() + 1);
-                    continue;
-                }
+                    self.add_insert(insert_str, parent_stmt.end_byte());
 
-                if let Some(parent_return) = get_parent_of_kind(&new, "return_statement") {
-                    let insert_str = format!(
-                        "{{ Value *V = {}; RC->trackDebugLocDst(V, nullptr, {}, {}, \"\", \"\"); ",
-                        new.to_source(code),
-                        ConstructKind::Creating,
+                    self.meta.add_site(
                         new.row(),
+                        SiteKind::Construct,
+                        var_name.to_source(code),
+                        vec![],
                     );
-                    self.add_insert(insert_str, parent_return.start_byte());
-
-                    let replace_str = format!("V");
-                    self.add_replace(replace_str, new.start_byte(), new.end_byte());
+                    continue;
+                }
 
-                    let insert_str = format!(" }}");
-                    self.add_insert(insert_str, parent_return.end_byte());
+                if let Some(parent_return) = new.parent().filter(|p| p.kind() == "return_statement")
+                {
+                    self.hoist_return_creation(&parent_return, &new, code);
                     continue;
                 }
 
-                println!(
-                    "{}{} {}:\n\t{} {}",
-                    "warning".yellow().bold(),
-                    ": Encounter an unsupported new expression at line".bold(),
-                    new.row(),
-                    "->".blue().bold(),
-                    new.to_source(code),
-                );
+                self.wrap_creation(&new, code);
             }
         }
     }
 
+    /// Hook the sites in the bodies of the lambdas, including the nested ones
+    fn visit_lambdas(&mut self, nodes: Vec<Node>, code: &str) {
+        for lambda in nodes {
+            let body = lambda.child_by_field_name("body").unwrap();
+            self.visit_fn_calls(
+                get_children_of_kind(&body, ASTNodeKind::CallExpr.into()),
+                code,
+            );
+            self.visit_new_exprs(
+                get_children_of_kind(&body, ASTNodeKind::NewExpr.into()),
+                code,
+            );
+        }
+    }
+
     fn visit_fn_defs(&mut self, nodes: Vec<Node>, code: &str) {
         for fn_def in nodes {
-            if get_children_of_kind(&fn_def, "function_declarator").len() == 0 {
+            let Some(fn_name) = get_fn_qualified_name(&fn_def, code) else {
                 println!(
                     "{}{} {}:\n\t{} {}",
                     "warning".yellow().bold(),
@@ -588,23 +1102,21 @@ impl Instrumenter {
                     fn_def.to_source(code)
                 );
                 continue;
-            }
-            let fn_ident = get_fn_identifier(&fn_def);
-            if fn_ident.to_source(code).is_pass_entry() {
+            };
+            if fn_name.is_pass_entry() {
                 /* Add initialization and clean up */
                 self.collect_init_and_clean_up_edit(&fn_def, code);
-            } else {
-                /* Process all function calls */
-                self.visit_fn_calls(
-                    get_children_of_kind(&fn_def, ASTNodeKind::CallExpr.into()),
-                    code,
-                );
-                /* Process all object news */
-                self.visit_new_exprs(
-                    get_children_of_kind(&fn_def, ASTNodeKind::NewExpr.into()),
-                    code,
-                );
             }
+            /* Process all function calls */
+            self.visit_fn_calls(
+                get_children_of_kind(&fn_def, ASTNodeKind::CallExpr.into()),
+                code,
+            );
+            /* Process all object news */
+            self.visit_new_exprs(
+                get_children_of_kind(&fn_def, ASTNodeKind::NewExpr.into()),
+                code,
+            );
         }
     }
 
@@ -632,5 +1144,276 @@ impl Instrumenter {
             get_children_of_kind(&root_node, ASTNodeKind::FnDef.into()),
             code,
         );
+
+        /* Instrument the lambdas outside the functions, e.g., `static auto F = [](..) {..};` */
+        self.visit_lambdas(
+            get_children_of_kind_in_scope(
+                &root_node,
+                ASTNodeKind::LambdaExpr.into(),
+                &[ASTNodeKind::FnDef.into()],
+            ),
+            code,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Instrument the code following the header include and the using declaration
+    fn instrument(code: &str) -> String {
+        let mut buf = format!(
+            "#include \"llvm/IR/Instructions.h\"\nusing namespace llvm;\n{}",
+            code
+        );
+        Instrumenter::new("FooPass.cpp".to_string(), None).instrument(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn wrap_creations_in_arguments() {
+        let out = instrument(
+            "void f(Type *Ty, Value *X, Instruction *I, SmallVector<PHINode *> &PNs) {\n\
+             \x20 PNs.push_back(PHINode::Create(Ty, 2));\n\
+             \x20 I->replaceAllUsesWith(BinaryOperator::CreateNeg(X));\n\
+             }\n",
+        );
+        assert!(out.contains(
+            "PNs.push_back([&] { auto *__disan_tmp_4_0 = PHINode::Create(Ty, 2); \
+             RC->trackDebugLocDst(__disan_tmp_4_0, nullptr, ConstructKind::Creating, 4, \
+             \"PHINode::Create(Ty, 2)\", \"\"); return __disan_tmp_4_0; }());"
+        ));
+        assert!(out.contains(
+            "Value *__disan_tmp_5_1 = [&] { auto *__disan_tmp_5_2 = BinaryOperator::CreateNeg(X); \
+             RC->trackDebugLocDst(__disan_tmp_5_2, nullptr, ConstructKind::Creating, 5, \
+             \"BinaryOperator::CreateNeg(X)\", \"\"); return __disan_tmp_5_2; }(); \
+             __disan_tmp_5_0->replaceAllUsesWith(__disan_tmp_5_1);"
+        ));
+    }
+
+    #[test]
+    fn skip_structured_bindings() {
+        let out = instrument(
+            "void f(Instruction *I, Instruction *J) {\n\
+             \x20 auto [X, Y] = std::make_pair(I, J);\n\
+             \x20 bool B = (X->replaceAllUsesWith(Y), true);\n\
+             \x20 X->replaceAllUsesWith(Y);\n\
+             \x20 foo(PHINode::Create(X->getType(), 2));\n\
+             }\n",
+        );
+        assert!(out.contains("bool B = (X->replaceAllUsesWith(Y), true);"));
+        assert!(out.contains("  foo(PHINode::Create(X->getType(), 2));\n"));
+        // A block captures nothing
+        assert!(out.contains("{ Value *__disan_tmp_6_0 = X; Value *__disan_tmp_6_1 = Y;"));
+    }
+
+    #[test]
+    fn bound_creations_to_their_statements() {
+        let out = instrument(
+            "void f(Value *X) {\n\
+             \x20 auto G = [&] { return BinaryOperator::CreateNeg(X); };\n\
+             \x20 if (auto *PN = dyn_cast<PHINode>(X))\n\
+             \x20   foo(PHINode::Create(PN->getType(), 2));\n\
+             }\n",
+        );
+        assert!(!out.contains("trackDebugLocDst(G,"));
+        assert!(out.contains(
+            "[&] { { auto *__disan_tmp_4_0 = BinaryOperator::CreateNeg(X); \
+             RC->trackDebugLocDst(__disan_tmp_4_0,"
+        ));
+        assert!(!out.contains("trackDebugLocDst(PN,"));
+        assert!(
+            out.contains("foo([&] { auto *__disan_tmp_6_0 = PHINode::Create(PN->getType(), 2);")
+        );
+    }
+
+    #[test]
+    fn track_clones_through_casts() {
+        let out = instrument(
+            "void f(Instruction *I) {\n\
+             \x20 auto *NI = cast<Instruction>(I->clone());\n\
+             \x20 PHINode *PN;\n\
+             \x20 PN = dyn_cast<PHINode>((I->clone()));\n\
+             \x20 foo(cast<Instruction>(I->clone()));\n\
+             }\n",
+        );
+        assert!(out.contains(
+            "auto *NI = cast<Instruction>(I->clone()); \
+             RC->trackDebugLocDst(NI, I, ConstructKind::Cloning, 4, \"NI\", \"I\");"
+        ));
+        assert!(out.contains(
+            "{ PN = dyn_cast<PHINode>((I->clone())); \
+             RC->trackDebugLocDst(PN, I, ConstructKind::Cloning, 6, \"PN\", \"I\"); }"
+        ));
+        assert!(out.contains("  foo(cast<Instruction>(I->clone()));"));
+    }
+
+    #[test]
+    fn hygienic_temporaries() {
+        let out = instrument(
+            "void f(Instruction *I, Value *V) {\n\
+             \x20 I->replaceAllUsesWith(V); BranchInst::Create(I->getParent());\n\
+             \x20 BranchInst::Create(I->getParent());\n\
+             }\n",
+        );
+        assert!(out.contains(
+            "{ Value *__disan_tmp_4_0 = I; Value *__disan_tmp_4_1 = V; \
+             __disan_tmp_4_0->replaceAllUsesWith(__disan_tmp_4_1);"
+        ));
+        assert!(out.contains("{ auto *__disan_tmp_4_2 = BranchInst::Create(I->getParent());"));
+        assert!(out.contains("{ auto *__disan_tmp_5_0 = BranchInst::Create(I->getParent());"));
+    }
+
+    #[test]
+    fn replace_in_expressions() {
+        let out = instrument(
+            "bool f(Instruction *I, Instruction *U, Value *V) {\n\
+             \x20 if (U->replaceUsesOfWith(I, V))\n\
+             \x20   I->replaceAllUsesWith(V);\n\
+             \x20 return (I->replaceAllUsesWith(V), true);\n\
+             }\n",
+        );
+        assert!(out.contains(
+            "if ([&] { auto *__disan_tmp_4_2 = U; Value *__disan_tmp_4_0 = I; \
+             Value *__disan_tmp_4_1 = V; auto __disan_tmp_4_3 = llvm::make_scope_exit([&] { \
+             RC->trackDebugLocSrc(__disan_tmp_4_1, __disan_tmp_4_0, 4, \"V\", \"I\"); }); \
+             return __disan_tmp_4_2->replaceUsesOfWith(__disan_tmp_4_0, __disan_tmp_4_1); }())"
+        ));
+        assert!(out.contains(
+            "    { Value *__disan_tmp_5_0 = I; Value *__disan_tmp_5_1 = V; \
+             __disan_tmp_5_0->replaceAllUsesWith(__disan_tmp_5_1); \
+             RC->trackDebugLocSrc(__disan_tmp_5_1, __disan_tmp_5_0, 5, \"V\", \"I\"); }\n"
+        ));
+        assert!(out.contains(
+            "return ([&] { Value *__disan_tmp_6_0 = I; Value *__disan_tmp_6_1 = V; \
+             __disan_tmp_6_0->replaceAllUsesWith(__disan_tmp_6_1); \
+             RC->trackDebugLocSrc(__disan_tmp_6_1, __disan_tmp_6_0, 6, \"V\", \"I\"); }(), true);"
+        ));
+    }
+
+    #[test]
+    fn skip_macro_arguments() {
+        let out = instrument(
+            "void f(Instruction *I, Value *V) {\n\
+             \x20 LLVM_DEBUG(I->replaceAllUsesWith(V));\n\
+             \x20 assert(BinaryOperator::CreateNeg(V));\n\
+             }\n",
+        );
+        assert!(out.contains("  LLVM_DEBUG(I->replaceAllUsesWith(V));\n"));
+        assert!(out.contains("  assert(BinaryOperator::CreateNeg(V));\n"));
+    }
+
+    #[test]
+    fn find_pass_entries() {
+        let out = instrument(
+            "struct FooPass : PassInfoMixin<FooPass> {\n\
+             \x20 PreservedAnalyses run(Function &F, FunctionAnalysisManager &AM) {\n\
+             \x20   return PreservedAnalyses::all();\n\
+             \x20 }\n\
+             };\n\
+             template <typename T> bool Foo<T>::bar(Instruction *I, Value *V) {\n\
+             \x20 I->replaceAllUsesWith(V);\n\
+             }\n",
+        );
+        assert!(out.contains("RC = new RuntimeChecker(F, \"FooPass.cpp\");"));
+        assert!(out.contains(
+            "{ auto __disan_tmp_5_0 = PreservedAnalyses::all(); \
+             RC->startCheck(); delete RC; return __disan_tmp_5_0; }"
+        ));
+        assert!(out.contains("{ Value *__disan_tmp_9_0 = I;"));
+    }
+
+    #[test]
+    fn hook_lambdas_in_pass_entry() {
+        let out = instrument(
+            "PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {\n\
+             \x20 auto G = [&](Instruction *I, Value *V) {\n\
+             \x20   I->replaceAllUsesWith(V);\n\
+             \x20   return true;\n\
+             \x20 };\n\
+             \x20 return G(nullptr, nullptr) ? PreservedAnalyses::none() : PreservedAnalyses::all();\n\
+             }\n",
+        );
+        assert!(out.contains("    { Value *__disan_tmp_5_0 = I;"));
+        // Only the returns of the pass entry run the check
+        assert!(out.contains("    return true;\n"));
+        assert!(out.contains(
+            "{ auto __disan_tmp_8_0 = G(nullptr, nullptr) ? PreservedAnalyses::none() : \
+             PreservedAnalyses::all(); RC->startCheck(); delete RC; return __disan_tmp_8_0; }"
+        ));
+    }
+
+    #[test]
+    fn insertion_overloads() {
+        let out = instrument(
+            "void f(Instruction *I, Instruction *J, BasicBlock *BB, BasicBlock::iterator It) {\n\
+             \x20 BB->getInstList().insert(BB->end(), I->clone());\n\
+             \x20 I->insertBefore(J);\n\
+             \x20 I->insertBefore(*BB, It);\n\
+             \x20 It = I->insertInto(BB, BB->end());\n\
+             }\n",
+        );
+        // The operands are evaluated once, e.g., a single clone
+        assert!(out.contains(
+            "{ auto &&__disan_tmp_4_0 = BB; auto &&__disan_tmp_4_1 = BB->end(); \
+             auto &&__disan_tmp_4_2 = I->clone(); RC->trackInsertion(__disan_tmp_4_2, \
+             *__disan_tmp_4_0, __disan_tmp_4_1, 4, \"I->clone()\", \"BB->end()\"); \
+             __disan_tmp_4_0->getInstList().insert(__disan_tmp_4_1, __disan_tmp_4_2); }"
+        ));
+        assert!(out.contains(
+            "RC->trackInsertion(__disan_tmp_5_0, __disan_tmp_5_1, 5, \"I\", \"J\"); \
+             __disan_tmp_5_0->insertBefore(__disan_tmp_5_1); }"
+        ));
+        assert!(out.contains(
+            "{ auto &&__disan_tmp_6_0 = I; auto &&__disan_tmp_6_1 = *BB; auto &&__disan_tmp_6_2 = It; \
+             RC->trackInsertion(__disan_tmp_6_0, __disan_tmp_6_1, __disan_tmp_6_2, 6, \"I\", \"It\");"
+        ));
+        assert!(out.contains(
+            "It = [&] { auto &&__disan_tmp_7_0 = I; auto &&__disan_tmp_7_1 = BB; \
+             auto &&__disan_tmp_7_2 = BB->end(); RC->trackInsertion(__disan_tmp_7_0, \
+             *__disan_tmp_7_1, __disan_tmp_7_2, 7, \"I\", \"BB->end()\"); \
+             return __disan_tmp_7_0->insertInto(__disan_tmp_7_1, __disan_tmp_7_2); }();"
+        ));
+    }
+
+    #[test]
+    fn move_overloads() {
+        let out = instrument(
+            "void f(Instruction &I, Instruction *J, BasicBlock &BB, BasicBlock::iterator It) {\n\
+             \x20 I.moveBefore(J);\n\
+             \x20 I.moveBefore(It);\n\
+             \x20 I.moveBefore(BB, BB.end());\n\
+             }\n",
+        );
+        assert!(out.contains(
+            "{ auto &&__disan_tmp_4_0 = I; auto &&__disan_tmp_4_1 = J; \
+             RC->trackDebugLocDst(&__disan_tmp_4_0, __disan_tmp_4_1, ConstructKind::Moving, 4, \
+             \"I\", \"J\"); __disan_tmp_4_0.moveBefore(__disan_tmp_4_1); }"
+        ));
+        assert!(out.contains(
+            "RC->trackDebugLocDst(&__disan_tmp_5_0, __disan_tmp_5_1, ConstructKind::Moving, 5,"
+        ));
+        assert!(out.contains(
+            "RC->trackDebugLocDst(&__disan_tmp_6_0, __disan_tmp_6_1, __disan_tmp_6_2, \
+             ConstructKind::Moving, 6, \"I\", \"BB.end()\");"
+        ));
+    }
+
+    #[test]
+    fn hook_insertion_in_pass_entry() {
+        let out = instrument(
+            "PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {\n\
+             \x20 BB->getInstList().insert(BB->end(), new StoreInst(V, V, I));\n\
+             \x20 return PreservedAnalyses::all();\n\
+             }\n",
+        );
+        assert!(out.contains(
+            "auto &&__disan_tmp_4_2 = [&] { auto *__disan_tmp_4_3 = new StoreInst(V, V, I); \
+             RC->trackDebugLocDst(__disan_tmp_4_3, nullptr, ConstructKind::Creating, 4, \
+             \"new StoreInst(V, V, I)\", \"\"); return __disan_tmp_4_3; }(); \
+             RC->trackInsertion(__disan_tmp_4_2, *__disan_tmp_4_0, __disan_tmp_4_1, 4, \
+             \"new StoreInst(V, V, I)\", \"BB->end()\");"
+        ));
     }
 }
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use crate::log::{LogRecord, Verdict};
+use crate::report::{collect_records, violation_message};
+use crate::sarif::rule_id;
+
+/// Module name of the records in the legacy log format, which lacks it
+const UNKNOWN_MODULE: &str = "<unknown module>";
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// One test case, i.e., the records of one module checked under one pass
+struct TestCase {
+    module: String,
+    records: Vec<LogRecord>,
+}
+
+impl TestCase {
+    fn failures(&self) -> Vec<&LogRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.verdict == Verdict::Fail)
+            .collect()
+    }
+
+    fn to_xml(&self, pass: &str) -> String {
+        let mut xml = format!(
+            "    <testcase classname=\"{}\" name=\"{}\">\n",
+            escape_xml(pass),
+            escape_xml(&self.module)
+        );
+
+        let failures = self.failures();
+        if !failures.is_empty() {
+            let details: Vec<String> = failures
+                .iter()
+                .map(|record| {
+                    format!(
+                        "{}:{}: {} [{}]\n{}",
+                        record.pass,
+                        record.construct_site,
+                        violation_message(record),
+                        rule_id(record),
+                        record
+                    )
+                })
+                .collect();
+            xml += &format!(
+                "      <failure message=\"{} violation(s)\" type=\"{}\">{}</failure>\n",
+                failures.len(),
+                escape_xml(&rule_id(failures[0])),
+                escape_xml(&details.join("\n\n"))
+            );
+        }
+
+        /* Warnings do not fail the test case, but are kept for inspection */
+        let warnings: Vec<String> = self
+            .records
+            .iter()
+            .filter(|record| record.verdict == Verdict::Warn)
+            .map(|record| record.to_string())
+            .collect();
+        if !warnings.is_empty() {
+            xml += &format!(
+                "      <system-out>{}</system-out>\n",
+                escape_xml(&warnings.join("\n"))
+            );
+        }
+
+        xml + "    </testcase>\n"
+    }
+}
+
+/// Convert the records to a JUnit XML report, with one test suite per pass
+/// and one test case per module (i.e., IR test file) checked under the pass.
+/// A test case fails if any violation is found in the module.
+pub fn to_junit(records: &[LogRecord]) -> String {
+    let mut by_pass: BTreeMap<&str, BTreeMap<&str, Vec<LogRecord>>> = BTreeMap::new();
+    for record in records {
+        let module = record.module.as_deref().unwrap_or(UNKNOWN_MODULE);
+        by_pass
+            .entry(&record.pass)
+            .or_default()
+            .entry(module)
+            .or_default()
+            .push(record.clone());
+    }
+
+    let mut suites = String::new();
+    let (mut total_tests, mut total_failures) = (0, 0);
+    for (pass, modules) in by_pass {
+        let cases: Vec<TestCase> = modules
+            .into_iter()
+            .map(|(module, records)| TestCase {
+                module: module.to_string(),
+                records: collect_records(records),
+            })
+            .collect();
+        let failures = cases
+            .iter()
+            .filter(|case| !case.failures().is_empty())
+            .count();
+        total_tests += cases.len();
+        total_failures += failures;
+
+        suites += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\">\n",
+            escape_xml(pass),
+            cases.len(),
+            failures
+        );
+        for case in &cases {
+            suites += &case.to_xml(pass);
+        }
+        suites += "  </testsuite>\n";
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuites name=\"DISan\" tests=\"{}\" failures=\"{}\" errors=\"0\">\n\
+         {}\
+         </testsuites>\n",
+        total_tests, total_failures, suites
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<LogRecord> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn suites_by_pass() {
+        let junit = to_junit(&records(&[
+            "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+            "warn: Drop [Construct: 20, Move; Pass: Foo.cpp]",
+            "pass: Preserve [Construct: 30, Create; Update: 31, Preserve; Pass: B&r.cpp]",
+        ]));
+        assert!(
+            junit.contains("<testsuites name=\"DISan\" tests=\"2\" failures=\"1\" errors=\"0\">")
+        );
+        assert!(junit.contains("<testsuite name=\"B&amp;r.cpp\" tests=\"1\" failures=\"0\""));
+        assert!(junit.contains("<testsuite name=\"Foo.cpp\" tests=\"1\" failures=\"1\""));
+        assert!(junit.contains("name=\"&lt;unknown module&gt;\""));
+        assert!(
+            junit.contains("<failure message=\"1 violation(s)\" type=\"DISAN-MISSING-PRESERVE\">")
+        );
+        assert!(junit
+            .contains("<system-out>warn: Drop [Construct: 20, Move; Pass: Foo.cpp]</system-out>"));
+    }
+}
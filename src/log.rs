@@ -0,0 +1,363 @@
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::matcher::{ConstructKind, DLUpdateKind};
+
+/// Version of the JSON Lines log schema, which must match `DISAN_LOG_SCHEMA` in the runtime library
+pub const LOG_SCHEMA_VERSION: u32 = 1;
+
+/// Verdict of the runtime checker on one debug location destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Verdict {
+    pub fn log_name(&self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Warn => "warn",
+            Verdict::Fail => "fail",
+        }
+    }
+}
+
+impl FromStr for Verdict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pass" => Ok(Verdict::Pass),
+            "warn" => Ok(Verdict::Warn),
+            "fail" => Ok(Verdict::Fail),
+            _ => Err(format!("No such verdict: {}", s)),
+        }
+    }
+}
+
+/// An instruction as printed by the runtime at a hook, e.g., the destination
+/// when it is created or updated, or a replaced instruction
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IrValue {
+    /// The source expression of the instruction, e.g., `NewI`
+    pub name: String,
+    pub ir: String,
+    /// The DILocation as `file:line:col`, if any
+    pub loc: Option<String>,
+    /// The hook site, absent for the destination
+    pub site: Option<usize>,
+}
+
+/// The IR around a violation, printed before the pass may erase it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IrContext {
+    /// The function holding the destination
+    pub function: String,
+    pub dst: Option<IrValue>,
+    /// The replaced instructions and the original one of a clone
+    #[serde(default)]
+    pub srcs: Vec<IrValue>,
+}
+
+/// One record of the runtime log, either a JSON line emitted by `DebugLocDstM::toJSON`
+/// (see [`JsonRecord`]) or a legacy text line emitted by `DebugLocDstM::toString`:
+///
+/// `fail: Preserve [Construct: 123, Clone; Replace: 130, 131; Update: 140, Drop; Pass: Foo.cpp]`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LogRecord {
+    pub verdict: Verdict,
+    /// The proper update kind decided by the runtime
+    pub expected: DLUpdateKind,
+    pub construct_site: usize,
+    pub construct_kind: ConstructKind,
+    /// Sorted, since the runtime emits them in hash order
+    pub replace_sites: Vec<usize>,
+    /// The update found in the code, if any
+    pub update: Option<(usize, DLUpdateKind)>,
+    /// File name of the pass source
+    pub pass: String,
+    /// The module and function checked, which the legacy format lacks
+    pub module: Option<String>,
+    pub function: Option<String>,
+    /// Where a clone is inserted, which the legacy format lacks
+    pub insert_site: Option<usize>,
+    /// The IR of the destination and its sources, which the legacy format lacks
+    pub context: Option<IrContext>,
+}
+
+impl LogRecord {
+    /// The record without the verdict, e.g., `Preserve [Construct: 123, Clone; Pass: Foo.cpp]`
+    pub fn body(&self) -> String {
+        let mut body = format!(
+            "{} [Construct: {}, {}",
+            self.expected.log_name(),
+            self.construct_site,
+            self.construct_kind.log_name(),
+        );
+        if !self.replace_sites.is_empty() {
+            let sites: Vec<String> = self.replace_sites.iter().map(|s| s.to_string()).collect();
+            body += &format!("; Replace: {}", sites.join(", "));
+        }
+        if let Some((update_site, update_kind)) = &self.update {
+            body += &format!("; Update: {}, {}", update_site, update_kind.log_name());
+        }
+        body + &format!("; Pass: {}]", self.pass)
+    }
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.verdict.log_name(), self.body())
+    }
+}
+
+fn parse_site(s: &str) -> Result<usize, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("Invalid source site: {}", s))
+}
+
+impl FromStr for LogRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (verdict, rest) = s
+            .split_once(": ")
+            .ok_or(format!("No verdict in the record: {}", s))?;
+        let (expected, rest) = rest
+            .split_once(" [")
+            .ok_or(format!("No proper update kind in the record: {}", s))?;
+        let fields = rest
+            .trim_end()
+            .strip_suffix(']')
+            .ok_or(format!("Unterminated record: {}", s))?;
+
+        let mut construct = None;
+        let mut replace_sites = vec![];
+        let mut update = None;
+        let mut pass = None;
+        for field in fields.split("; ") {
+            let (key, value) = field
+                .split_once(": ")
+                .ok_or(format!("Invalid field `{}` in the record: {}", field, s))?;
+            match key {
+                "Construct" => {
+                    let (site, kind) = value
+                        .split_once(", ")
+                        .ok_or(format!("Invalid construct field: {}", value))?;
+                    construct = Some((parse_site(site)?, kind.parse()?));
+                }
+                "Replace" => {
+                    replace_sites = value
+                        .split(", ")
+                        .map(parse_site)
+                        .collect::<Result<_, _>>()?;
+                    replace_sites.sort();
+                }
+                "Update" => {
+                    let (site, kind) = value
+                        .split_once(", ")
+                        .ok_or(format!("Invalid update field: {}", value))?;
+                    update = Some((parse_site(site)?, kind.parse()?));
+                }
+                "Pass" => pass = Some(value.to_string()),
+                _ => return Err(format!("Unknown field `{}` in the record: {}", key, s)),
+            }
+        }
+
+        let (construct_site, construct_kind) =
+            construct.ok_or(format!("No construct field in the record: {}", s))?;
+        Ok(LogRecord {
+            verdict: verdict.parse()?,
+            expected: expected.parse()?,
+            construct_site,
+            construct_kind,
+            replace_sites,
+            update,
+            pass: pass.ok_or(format!("No pass field in the record: {}", s))?,
+            module: None,
+            function: None,
+            insert_site: None,
+            context: None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonConstruct {
+    pub kind: ConstructKind,
+    pub site: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsonUpdate {
+    pub kind: DLUpdateKind,
+    pub site: usize,
+}
+
+/// One line of the JSON Lines runtime log, e.g.,
+///
+/// `{"schema":1,"verdict":"fail","pass":"Foo.cpp","module":"test.ll","function":"f",
+/// "construct":{"kind":"Clone","site":123},"replace_sites":[130,131],"insert_site":125,
+/// "expected":"Preserve","update":{"kind":"Drop","site":140},
+/// "context":{"function":"f","dst":{"name":"NewI","ir":"%x = ...","loc":"a.c:3:5"},"srcs":[...]}}`
+#[derive(Serialize, Deserialize)]
+pub struct JsonRecord {
+    /// Bumped on every incompatible change, see [`LOG_SCHEMA_VERSION`]
+    pub schema: u32,
+    pub verdict: Verdict,
+    pub pass: String,
+    pub module: String,
+    pub function: String,
+    pub construct: JsonConstruct,
+    #[serde(default)]
+    pub replace_sites: Vec<usize>,
+    pub insert_site: Option<usize>,
+    /// The proper update kind decided by the runtime
+    pub expected: DLUpdateKind,
+    /// The update found in the code, if any
+    pub update: Option<JsonUpdate>,
+    /// Absent in the logs of the runtimes predating it
+    #[serde(default)]
+    pub context: Option<IrContext>,
+}
+
+impl From<JsonRecord> for LogRecord {
+    fn from(json: JsonRecord) -> Self {
+        let mut replace_sites = json.replace_sites;
+        replace_sites.sort();
+        LogRecord {
+            verdict: json.verdict,
+            expected: json.expected,
+            construct_site: json.construct.site,
+            construct_kind: json.construct.kind,
+            replace_sites,
+            update: json.update.map(|update| (update.site, update.kind)),
+            pass: json.pass,
+            module: Some(json.module),
+            function: Some(json.function),
+            insert_site: json.insert_site,
+            context: json.context,
+        }
+    }
+}
+
+/// Parse a JSON line of the runtime log, rejecting the schemas newer than this tool
+fn parse_json_record(line: &str) -> Result<LogRecord, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let schema = value
+        .get("schema")
+        .and_then(|schema| schema.as_u64())
+        .ok_or("No schema version in the record")?;
+    if schema > LOG_SCHEMA_VERSION as u64 {
+        return Err(format!(
+            "Unsupported schema version {} (at most {}), please update DISan",
+            schema, LOG_SCHEMA_VERSION
+        ));
+    }
+    let record: JsonRecord =
+        serde_json::from_value(value).map_err(|e| format!("Invalid record: {}", e))?;
+    Ok(record.into())
+}
+
+/// Parse all the records in a runtime log of either format, warning about the malformed lines
+pub fn parse_log(content: &str) -> Vec<LogRecord> {
+    let mut records = vec![];
+    for (idx, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = if line.trim_start().starts_with('{') {
+            parse_json_record(line)
+        } else {
+            line.parse()
+        };
+        match record {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!(
+                "{}{} {}: {}",
+                "warning".yellow().bold(),
+                ": Skip a malformed record at line".bold(),
+                idx + 1,
+                e,
+            ),
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY: &str =
+        "fail: Preserve [Construct: 123, Clone; Replace: 131, 130; Update: 140, Drop; Pass: Foo.cpp]";
+
+    const JSONL: &str = r#"{"schema":1,"verdict":"warn","pass":"Foo.cpp","module":"test.ll","function":"f","construct":{"kind":"Move","site":12},"replace_sites":[20],"insert_site":null,"expected":"Merge","update":null,"context":{"function":"f","dst":{"name":"I","ir":"%x = add i32 %a, %b","loc":"a.c:3:5","site":null},"srcs":[]}}"#;
+
+    #[test]
+    fn legacy_record() {
+        let record: LogRecord = LEGACY.parse().unwrap();
+        assert_eq!(record.verdict, Verdict::Fail);
+        assert_eq!(record.expected, DLUpdateKind::Preserving);
+        assert_eq!(record.construct_site, 123);
+        assert_eq!(record.construct_kind, ConstructKind::Cloning);
+        assert_eq!(record.replace_sites, [130, 131]);
+        assert_eq!(record.update, Some((140, DLUpdateKind::Dropping)));
+        assert_eq!(record.pass, "Foo.cpp");
+        assert_eq!(record.module, None);
+        assert_eq!(record.to_string(), LEGACY.replace("131, 130", "130, 131"));
+    }
+
+    #[test]
+    fn json_record() {
+        let record = parse_json_record(JSONL).unwrap();
+        assert_eq!(record.verdict, Verdict::Warn);
+        assert_eq!(record.expected, DLUpdateKind::Merging);
+        assert_eq!(record.construct_kind, ConstructKind::Moving);
+        assert_eq!(record.replace_sites, [20]);
+        assert_eq!(record.update, None);
+        assert_eq!(record.module.as_deref(), Some("test.ll"));
+        let dst = record.context.unwrap().dst.unwrap();
+        assert_eq!(dst.loc.as_deref(), Some("a.c:3:5"));
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let newer = JSONL.replace(r#""schema":1"#, r#""schema":99"#);
+        assert!(parse_json_record(&newer).is_err());
+    }
+
+    #[test]
+    fn mixed_log_skips_malformed_lines() {
+        let log = format!(
+            "{}\n\nfail: Preserve [Construct: 1, Create\nnot a record\n{{\"schema\":1}}\n{}\n",
+            LEGACY, JSONL
+        );
+        let records = parse_log(&log);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].construct_site, 123);
+        assert_eq!(records[1].construct_site, 12);
+    }
+
+    #[test]
+    fn malformed_legacy_records() {
+        for line in [
+            "fail Preserve [Construct: 1, Create; Pass: Foo.cpp]",
+            "fail: Preserve [Construct: x, Create; Pass: Foo.cpp]",
+            "fail: Preserve [Construct: 1, Create; Pass: Foo.cpp",
+            "fail: Preserve [Pass: Foo.cpp]",
+            "fail: Preserve [Construct: 1, Create]",
+            "bad: Preserve [Construct: 1, Create; Pass: Foo.cpp]",
+            "fail: Preserve [Construct: 1, Create; Color: red; Pass: Foo.cpp]",
+        ] {
+            assert!(line.parse::<LogRecord>().is_err(), "{}", line);
+        }
+    }
+}
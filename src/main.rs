@@ -1,37 +1,174 @@
 mod ast;
+mod baseline;
+mod diff;
 mod edit;
+mod fingerprint;
 mod hook;
+mod html;
 mod instrument;
+mod junit;
+mod log;
 mod matcher;
+mod metadata;
+mod preproc;
+mod report;
+mod runtime;
+mod sarif;
+mod source;
+mod suggest;
 mod traverse;
+mod types;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use std::{
+    collections::BTreeSet,
     fs,
     path::{Path, PathBuf},
+    process,
 };
 
+use baseline::Baseline;
 use colored::Colorize;
 use hook::Hook;
 use instrument::Instrumenter;
+use metadata::InstrumentMeta;
+use preproc::CompileDb;
+use source::SourceRoot;
 
 const OUTPUT_DIR: &str = "./instrumented/";
 
 #[derive(Parser)]
-#[command(name = "DISan")]
+#[command(name = "DISan", args_conflicts_with_subcommands = true)]
 struct DISan {
-    target: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The pass source file, or the directory of pass source files, to instrument
+    target: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Instrument a pass source file or all the pass source files in a directory
+    Instrument {
+        target: String,
+        /// Root of the LLVM project, whose installed runtime library is checked to match
+        #[arg(long)]
+        llvm_root: Option<PathBuf>,
+        /// The `compile_commands.json` of the LLVM build (or its build directory), to skip
+        /// the sites in the branches not taken by the build and in the debug-only macros
+        #[arg(long)]
+        compile_commands: Option<PathBuf>,
+    },
+    /// Summarize the runtime log produced by instrumented passes
+    Report {
+        log: PathBuf,
+        /// Directory searched for the original pass sources to annotate the violations
+        #[arg(long)]
+        source_root: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+        /// Suppress the known violations in the baseline, and fail only on the new ones
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+    },
+    /// Compare the runtime logs of two runs, e.g., on two revisions of LLVM
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Directory of the pass sources of the old run, to align the records by fingerprints
+        #[arg(long)]
+        old_source_root: Option<PathBuf>,
+        /// Directory of the pass sources of the new run, to align the records by fingerprints
+        #[arg(long)]
+        new_source_root: Option<PathBuf>,
+    },
+    /// Manage the baseline of known violations
+    Baseline {
+        #[command(subcommand)]
+        command: BaselineCommand,
+    },
+    /// Suggest patches for the violations in the runtime log as unified diffs
+    Suggest {
+        log: PathBuf,
+        /// Directory searched for the original pass sources to patch
+        #[arg(long)]
+        source_root: PathBuf,
+        /// Directory of the instrumentation metadata, i.e., the instrumentation output directory
+        #[arg(long, default_value = OUTPUT_DIR)]
+        meta_dir: PathBuf,
+    },
+    /// Fix the violations in the runtime log by patching the pass sources
+    Fix {
+        log: PathBuf,
+        /// Directory searched for the original pass sources to patch
+        #[arg(long)]
+        source_root: PathBuf,
+        /// Directory of the instrumentation metadata, i.e., the instrumentation output directory
+        #[arg(long, default_value = OUTPUT_DIR)]
+        meta_dir: PathBuf,
+        /// Write the patches into the pass sources instead of listing them
+        #[arg(long)]
+        apply: bool,
+        /// Also write one `git format-patch`-style mail per violation into this directory
+        #[arg(long)]
+        format_patch: Option<PathBuf>,
+    },
+    /// Copy the runtime library into an LLVM project and add it to the build
+    InstallRuntime {
+        /// Root of the LLVM project, i.e., the directory holding `llvm/`
+        #[arg(long)]
+        llvm_root: PathBuf,
+        /// Directory of the runtime logs, or `-` for stderr [default: /tmp/disan/]
+        #[arg(long)]
+        log_dir: Option<String>,
+        /// Name the runtime logs by process id as well, e.g., for parallel lit workers
+        #[arg(long)]
+        log_per_process: bool,
+    },
+    /// Remove the runtime library from an LLVM project
+    UninstallRuntime {
+        /// Root of the LLVM project, i.e., the directory holding `llvm/`
+        #[arg(long)]
+        llvm_root: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum BaselineCommand {
+    /// Regenerate the baseline from the fail and warn records in the runtime log
+    Update {
+        log: PathBuf,
+        #[arg(long, default_value = "baseline.json")]
+        baseline: PathBuf,
+        /// Directory searched for the original pass sources to fingerprint the sites,
+        /// without which the sites are keyed by line numbers
+        #[arg(long)]
+        source_root: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    /// Colored diagnostics for the terminal
+    Text,
+    /// SARIF 2.1.0, for code review tooling
+    Sarif,
+    /// JUnit XML with one test case per module and pass, for CI
+    Junit,
+    /// A self-contained HTML page for triage
+    Html,
 }
 
 fn check_code(buf: &str, report: bool) -> bool {
     let mut check_pass = true;
 
-    check_pass = check_pass && buf.contains(&Hook::header_include());
+    check_pass = check_pass && buf.contains(Hook::header_include());
     if !check_pass && report {
         println!("{}", "No instrument header!".red().bold());
     }
 
-    check_pass = check_pass && buf.contains(&Hook::global_var_decl());
+    check_pass = check_pass && buf.contains(Hook::global_var_decl());
     if !check_pass && report {
         println!("{}", "No instrument global variable!".red().bold());
     }
@@ -49,31 +186,78 @@ fn check_code(buf: &str, report: bool) -> bool {
     check_pass
 }
 
+/// Whether the file has been instrumented, by any version of DISan
+fn is_instrumented(buf: &str) -> bool {
+    buf.contains(Hook::header_include().trim_end()) || buf.contains("RC = new RuntimeChecker")
+}
+
+/// The problem of the runtime ABI check of an instrumented file, if any, e.g.,
+/// of a file instrumented before the check or against an older ABI
+fn abi_check_error(buf: &str) -> Option<&'static str> {
+    if buf.contains(&Hook::abi_check()) {
+        None
+    } else if buf.contains("static_assert(DISAN_RUNTIME_ABI") {
+        Some("Stale runtime ABI check!")
+    } else {
+        Some("No runtime ABI check!")
+    }
+}
+
+fn check_abi(buf: &str, report: bool) -> bool {
+    let error = abi_check_error(buf);
+    if let Some(error) = error.filter(|_| report) {
+        println!("{}", error.red().bold());
+    }
+    error.is_none()
+}
+
 fn write_code(contents: &str, file_name: &str) {
     let path = OUTPUT_DIR.to_string() + file_name;
     fs::write(path, contents).unwrap();
 }
 
-fn instrument_code(path: &PathBuf) {
+fn instrument_code(path: &PathBuf, compile_db: Option<&CompileDb>) {
     let absolute_path = path.canonicalize().unwrap();
-    let mut code = fs::read_to_string(&path).unwrap();
+    let mut code = fs::read_to_string(path).unwrap();
     let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
     let file_str = absolute_path.to_str().unwrap();
 
-    if check_code(&code, false) {
+    if is_instrumented(&code) {
         println!(
             "{} ({})",
             "The file has already been instrumented!".red().bold(),
             &file_str
         );
+        if !check_abi(&code, true) {
+            println!(
+                "{}",
+                "Instrument the original pass source again for the current runtime.".bold()
+            );
+        }
         return;
     }
 
-    let mut instrumenter = Instrumenter::new(file_name.to_owned());
+    let defines = compile_db.and_then(|compile_db| {
+        let defines = compile_db.defines_of(path);
+        if defines.is_none() {
+            eprintln!(
+                "{}{} {}",
+                "warning".yellow().bold(),
+                ": No compile command of the pass source, hooking all the sites:".bold(),
+                file_str,
+            );
+        }
+        defines
+    });
+    let mut instrumenter = Instrumenter::new(file_name.to_owned(), defines);
     instrumenter.instrument(&mut code);
 
-    if check_code(&code, true) {
+    if check_code(&code, true) && check_abi(&code, true) {
         write_code(&code, &file_name);
+        let meta_path = InstrumentMeta::path_of(Path::new(OUTPUT_DIR), &file_name);
+        if let Err(e) = instrumenter.meta().save(&meta_path) {
+            eprintln!("{}", e);
+        }
         println!(
             "{} ({})",
             "Finished the instrumentation!".green().bold(),
@@ -88,11 +272,255 @@ fn instrument_code(path: &PathBuf) {
     }
 }
 
-fn main() {
-    let disan = DISan::parse();
-    let path = Path::new(&disan.target);
+/// Read the records in a runtime log, without deduplicating them
+fn read_log(log_path: &Path) -> Option<Vec<log::LogRecord>> {
+    match fs::read_to_string(log_path) {
+        Ok(content) => Some(log::parse_log(&content)),
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", log_path.display(), e);
+            None
+        }
+    }
+}
+
+fn read_records(log_path: &Path) -> Option<Vec<log::LogRecord>> {
+    read_log(log_path).map(report::collect_records)
+}
+
+fn warn_missing_sources(records: &[log::LogRecord], source_root: &mut SourceRoot) {
+    let passes: BTreeSet<&String> = records.iter().map(|record| &record.pass).collect();
+    for pass in passes {
+        if source_root.get(pass).is_none() {
+            eprintln!(
+                "{}{} {}",
+                "warning".yellow().bold(),
+                ": Cannot find the pass source under the source root:".bold(),
+                pass,
+            );
+        }
+    }
+}
+
+/// Report the records in the log. With a baseline, the known violations are
+/// suppressed and false is returned if there are any new ones.
+fn report_log(
+    log_path: &Path,
+    source_root: Option<&Path>,
+    format: ReportFormat,
+    baseline: Option<&Path>,
+) -> bool {
+    let Some(records) = read_log(log_path) else {
+        return false;
+    };
+    let mut source_root = source_root.map(SourceRoot::new);
+    if let Some(source_root) = &mut source_root {
+        warn_missing_sources(&records, source_root);
+    }
+
+    let baseline = match baseline.map(Baseline::load) {
+        Some(Ok(baseline)) => Some(baseline),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return false;
+        }
+        None => None,
+    };
+    /* Only the new violations are reported against a baseline */
+    let (records, suppressed) = match &baseline {
+        Some(baseline) => baseline.filter(records, source_root.as_mut()),
+        None => (records, 0),
+    };
+    let passed = baseline.is_none() || records.is_empty();
+
+    match format {
+        ReportFormat::Text => {
+            let records = report::collect_records(records);
+            report::print_report(&records, source_root.as_mut());
+            if baseline.is_some() {
+                println!("Suppressed by the baseline: {}", suppressed);
+            }
+        }
+        ReportFormat::Sarif => {
+            let records = report::collect_records(records);
+            let sarif = sarif::to_sarif(&records, source_root.as_mut());
+            println!("{}", serde_json::to_string_pretty(&sarif).unwrap());
+        }
+        ReportFormat::Html => {
+            let records = report::collect_records(records);
+            print!("{}", html::to_html(&records, source_root.as_mut()));
+        }
+        /* Test cases are per module, so the records are deduplicated per module */
+        ReportFormat::Junit => print!("{}", junit::to_junit(&records)),
+    }
+    passed
+}
+
+fn diff_logs(
+    old_path: &Path,
+    new_path: &Path,
+    old_root: Option<&Path>,
+    new_root: Option<&Path>,
+) -> bool {
+    let (Some(old), Some(new)) = (read_records(old_path), read_records(new_path)) else {
+        return false;
+    };
+    let mut old_root = old_root.map(SourceRoot::new);
+    let mut new_root = new_root.map(SourceRoot::new);
+    if let Some(old_root) = &mut old_root {
+        warn_missing_sources(&old, old_root);
+    }
+    if let Some(new_root) = &mut new_root {
+        warn_missing_sources(&new, new_root);
+    }
+
+    let changes = diff::diff_runs(&old, old_root.as_mut(), &new, new_root.as_mut());
+    diff::print_diff(&old, &new, &changes);
+    true
+}
+
+fn update_baseline(log_path: &Path, baseline_path: &Path, source_root: Option<&Path>) -> bool {
+    let Some(records) = read_records(log_path) else {
+        return false;
+    };
+    let mut source_root = source_root.map(SourceRoot::new);
+    if let Some(source_root) = &mut source_root {
+        warn_missing_sources(&records, source_root);
+    }
+
+    let baseline = Baseline::from_records(&records, source_root.as_mut());
+    match baseline.save(baseline_path) {
+        Ok(()) => {
+            println!(
+                "{} ({} known violations, {})",
+                "Updated the baseline!".green().bold(),
+                baseline.entries.len(),
+                baseline_path.display()
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    }
+}
+
+fn suggest_patches(log_path: &Path, root: &Path, meta_dir: &Path) {
+    let Some(records) = read_records(log_path) else {
+        return;
+    };
+
+    let mut source_root = SourceRoot::new(root);
+    for pass in suggest::collect_suggestions(&records, &mut source_root, meta_dir) {
+        for (record, suggestion) in &pass.suggestions {
+            println!("# {}", record);
+            println!("# Suggestion: {}", suggestion.summary);
+            print!(
+                "{}",
+                suggest::to_unified_diff(suggestion, &pass.source, &pass.label)
+            );
+        }
+    }
+}
+
+/// Author of the emitted patches, as configured for git
+fn patch_author(root: &Path) -> String {
+    let ident = process::Command::new("git")
+        .args(["var", "GIT_AUTHOR_IDENT"])
+        .current_dir(root)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+    /* Strip the trailing timestamp and timezone */
+    ident
+        .and_then(|ident| Some(ident[..=ident.rfind('>')?].to_string()))
+        .unwrap_or("DISan <disan@localhost>".to_string())
+}
+
+fn fix_violations(
+    log_path: &Path,
+    root: &Path,
+    meta_dir: &Path,
+    apply: bool,
+    format_patch: Option<&Path>,
+) {
+    let Some(records) = read_records(log_path) else {
+        return;
+    };
+
+    let mut source_root = SourceRoot::new(root);
+    let passes = suggest::collect_suggestions(&records, &mut source_root, meta_dir);
+
+    if let Some(patch_dir) = format_patch {
+        if let Err(e) = fs::create_dir_all(patch_dir) {
+            eprintln!("Failed to create {}: {}", patch_dir.display(), e);
+            return;
+        }
+        let author = patch_author(root);
+        let total: usize = passes.iter().map(|pass| pass.suggestions.len()).sum();
+        let all = passes
+            .iter()
+            .flat_map(|pass| pass.suggestions.iter().map(move |s| (pass, s)));
+        for (idx, (pass, (record, suggestion))) in (1..).zip(all) {
+            let patch = suggest::to_format_patch(
+                suggestion,
+                record,
+                &pass.source,
+                &pass.label,
+                &author,
+                (idx, total),
+            );
+            let stem = pass.label.replace(['/', '.'], "-");
+            let patch_path = patch_dir.join(format!(
+                "{:04}-{}-{}.patch",
+                idx, stem, record.construct_site
+            ));
+            match fs::write(&patch_path, patch) {
+                Ok(()) => println!("Write {}", patch_path.display()),
+                Err(e) => eprintln!("Failed to write {}: {}", patch_path.display(), e),
+            }
+        }
+    }
+
+    for pass in &passes {
+        if pass.suggestions.is_empty() {
+            continue;
+        }
+        if !apply {
+            for (record, suggestion) in &pass.suggestions {
+                println!("{}: {} ({})", pass.label, suggestion.summary, record.body());
+            }
+            continue;
+        }
+
+        let suggestions: Vec<_> = pass.suggestions.iter().map(|(_, s)| s).collect();
+        let patched = match suggest::apply_suggestions(&pass.source, &suggestions) {
+            Ok(patched) => patched,
+            Err(e) => {
+                eprintln!("{} {}", "Refused to apply the patches:".red().bold(), e);
+                continue;
+            }
+        };
+        match fs::write(&pass.source.path, patched) {
+            Ok(()) => println!(
+                "{} ({} patches, {})",
+                "Applied the patches!".green().bold(),
+                suggestions.len(),
+                pass.source.path.display()
+            ),
+            Err(e) => eprintln!("Failed to write {}: {}", pass.source.path.display(), e),
+        }
+    }
+    if !apply {
+        println!("\nDry run. Use `--apply` to write the patches into the pass sources.");
+    }
+}
+
+fn instrument_target(target: &str, compile_db: Option<&CompileDb>) {
+    let path = Path::new(target);
     if !path.exists() {
-        eprintln!("{} does not exist!", &disan.target);
+        eprintln!("{} does not exist!", target);
         return;
     }
 
@@ -103,12 +531,10 @@ fn main() {
     }
 
     if path.is_dir() {
-        for entry in path.read_dir().expect("Failed to read the dir") {
-            if let Ok(e) = entry {
-                let file_path = e.path();
-                if file_path.is_file() && file_path.extension().unwrap() == "cpp" {
-                    work_list.push(file_path);
-                }
+        for entry in path.read_dir().expect("Failed to read the dir").flatten() {
+            let file_path = entry.path();
+            if file_path.is_file() && file_path.extension().unwrap() == "cpp" {
+                work_list.push(file_path);
             }
         }
     }
@@ -126,9 +552,161 @@ fn main() {
         };
     }
 
-    work_list.iter().for_each(instrument_code);
+    for path in &work_list {
+        instrument_code(path, compile_db);
+    }
 
     if output_dir.read_dir().expect("Error").count() == 0 {
         fs::remove_dir(output_dir).unwrap();
     }
 }
+
+fn main() {
+    let disan = DISan::parse();
+    match disan.command {
+        Some(Command::Instrument {
+            target,
+            llvm_root,
+            compile_commands,
+        }) => {
+            if let Some(llvm_root) = llvm_root {
+                if let Err(e) = runtime::LlvmTree::open(&llvm_root)
+                    .and_then(|tree| runtime::check_installed(&tree))
+                {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            let compile_db = compile_commands.map(|path| {
+                CompileDb::load(&path).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(1)
+                })
+            });
+            instrument_target(&target, compile_db.as_ref())
+        }
+        Some(Command::Report {
+            log,
+            source_root,
+            format,
+            baseline,
+        }) => {
+            if !report_log(&log, source_root.as_deref(), format, baseline.as_deref()) {
+                process::exit(1);
+            }
+        }
+        Some(Command::Diff {
+            old,
+            new,
+            old_source_root,
+            new_source_root,
+        }) => {
+            if !diff_logs(
+                &old,
+                &new,
+                old_source_root.as_deref(),
+                new_source_root.as_deref(),
+            ) {
+                process::exit(1);
+            }
+        }
+        Some(Command::Baseline {
+            command:
+                BaselineCommand::Update {
+                    log,
+                    baseline,
+                    source_root,
+                },
+        }) => {
+            if !update_baseline(&log, &baseline, source_root.as_deref()) {
+                process::exit(1);
+            }
+        }
+        Some(Command::Suggest {
+            log,
+            source_root,
+            meta_dir,
+        }) => suggest_patches(&log, &source_root, &meta_dir),
+        Some(Command::Fix {
+            log,
+            source_root,
+            meta_dir,
+            apply,
+            format_patch,
+        }) => fix_violations(
+            &log,
+            &source_root,
+            &meta_dir,
+            apply,
+            format_patch.as_deref(),
+        ),
+        Some(Command::InstallRuntime {
+            llvm_root,
+            log_dir,
+            log_per_process,
+        }) => {
+            let config = runtime::RuntimeConfig {
+                log_dir,
+                per_process: log_per_process,
+            };
+            if let Err(e) = runtime::LlvmTree::open(&llvm_root)
+                .and_then(|tree| runtime::install(&tree, &config))
+            {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(Command::UninstallRuntime { llvm_root }) => {
+            if let Err(e) =
+                runtime::LlvmTree::open(&llvm_root).and_then(|tree| runtime::uninstall(&tree))
+            {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        None => match disan.target {
+            Some(target) => instrument_target(&target, None),
+            None => eprintln!("No target to instrument! See `--help` for usage."),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "\
+#include \"llvm/Transforms/Utils/RuntimeChecker.h\"
+#include \"llvm/IR/Instructions.h\"
+using namespace llvm;
+namespace { RuntimeChecker *RC = nullptr; }
+";
+
+    #[test]
+    fn instrumented_without_abi_check() {
+        assert!(is_instrumented(OLD));
+        assert!(is_instrumented(
+            "  RC = new RuntimeChecker(F, \"Foo.cpp\");"
+        ));
+        assert!(!is_instrumented("#include \"llvm/IR/Instructions.h\"\n"));
+        assert_eq!(abi_check_error(OLD), Some("No runtime ABI check!"));
+    }
+
+    #[test]
+    fn stale_abi_check() {
+        let stale = format!(
+            "{}static_assert(DISAN_RUNTIME_ABI == {}, \"\");\n",
+            OLD,
+            hook::RUNTIME_ABI_VERSION - 1
+        );
+        assert_eq!(abi_check_error(&stale), Some("Stale runtime ABI check!"));
+        assert!(!check_abi(&stale, false));
+    }
+
+    #[test]
+    fn current_abi_check() {
+        let current = format!("{}{}", Hook::header_include(), Hook::abi_check());
+        assert_eq!(abi_check_error(&current), None);
+        assert!(check_abi(&current, true));
+    }
+}
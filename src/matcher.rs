@@ -1,10 +1,31 @@
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::str::FromStr;
 
-#[derive(PartialEq)]
+/// Mirror of `UpdateKind` in the runtime library, serialized by the names in the runtime logs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum DLUpdateKind {
+    #[serde(rename = "Preserve")]
     Preserving,
+    #[serde(rename = "Merge")]
     Merging,
+    #[serde(rename = "Drop")]
     Dropping,
+    /// Any update is acceptable, e.g., for untracked instructions
+    #[serde(rename = "Any")]
+    Others,
+}
+
+impl DLUpdateKind {
+    /// The name used in the runtime logs (`UKindToString`)
+    pub fn log_name(&self) -> &'static str {
+        match self {
+            DLUpdateKind::Preserving => "Preserve",
+            DLUpdateKind::Merging => "Merge",
+            DLUpdateKind::Dropping => "Drop",
+            DLUpdateKind::Others => "Any",
+        }
+    }
 }
 
 impl Display for DLUpdateKind {
@@ -16,16 +37,49 @@ impl Display for DLUpdateKind {
                 DLUpdateKind::Preserving => "UpdateKind::Preserving",
                 DLUpdateKind::Merging => "UpdateKind::Merging",
                 DLUpdateKind::Dropping => "UpdateKind::Dropping",
+                DLUpdateKind::Others => "UpdateKind::Others",
             }
         )
     }
 }
 
-#[derive(PartialEq)]
+impl FromStr for DLUpdateKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Preserve" => Ok(DLUpdateKind::Preserving),
+            "Merge" => Ok(DLUpdateKind::Merging),
+            "Drop" => Ok(DLUpdateKind::Dropping),
+            "Any" => Ok(DLUpdateKind::Others),
+            _ => Err(format!("No such update kind: {}", s)),
+        }
+    }
+}
+
+/// Mirror of `ConstructKind` in the runtime library, serialized by the names in the runtime logs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ConstructKind {
+    #[serde(rename = "Create")]
     Creating,
+    #[serde(rename = "Clone")]
     Cloning,
+    #[serde(rename = "Move")]
     Moving,
+    /// Instructions only seen in replacements, which are never emitted by the instrumenter
+    Untracked,
+}
+
+impl ConstructKind {
+    /// The name used in the runtime logs (`CKindToString`)
+    pub fn log_name(&self) -> &'static str {
+        match self {
+            ConstructKind::Creating => "Create",
+            ConstructKind::Cloning => "Clone",
+            ConstructKind::Moving => "Move",
+            ConstructKind::Untracked => "Untracked",
+        }
+    }
 }
 
 impl Display for ConstructKind {
@@ -37,17 +91,40 @@ impl Display for ConstructKind {
                 ConstructKind::Creating => "ConstructKind::Creating",
                 ConstructKind::Moving => "ConstructKind::Moving",
                 ConstructKind::Cloning => "ConstructKind::Cloning",
+                ConstructKind::Untracked => "ConstructKind::Untracked",
             }
         )
     }
 }
 
+impl FromStr for ConstructKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Create" => Ok(ConstructKind::Creating),
+            "Clone" => Ok(ConstructKind::Cloning),
+            "Move" => Ok(ConstructKind::Moving),
+            "Untracked" => Ok(ConstructKind::Untracked),
+            _ => Err(format!("No such construct kind: {}", s)),
+        }
+    }
+}
+
 pub trait FuncMatch {
     fn is_construct(&self) -> Option<ConstructKind>;
     fn is_replacement(&self) -> bool;
     fn is_debugloc_update(&self) -> Option<DLUpdateKind>;
     fn is_pass_entry(&self) -> bool;
     fn is_insertion(&self) -> bool;
+
+    /// Whether the calls to the function are hooked by any kind
+    fn is_hooked(&self) -> bool {
+        self.is_construct().is_some()
+            || self.is_replacement()
+            || self.is_debugloc_update().is_some()
+            || self.is_insertion()
+    }
 }
 
 const CREATE_FUNC: [&str; 37] = [
@@ -96,7 +173,7 @@ const MOVE_FUNC: [&str; 3] = ["moveBefore", "moveBeforePreserving", "moveAfter"]
 
 const INSERT_FUNC: [&str; 3] = ["insertBefore", "insertAfter", "insertInto"];
 
-impl FuncMatch for String {
+impl FuncMatch for str {
     fn is_construct(&self) -> Option<ConstructKind> {
         for prefix in CREATE_FUNC {
             if prefix.contains("::") {
@@ -104,15 +181,15 @@ impl FuncMatch for String {
                     return Some(ConstructKind::Creating);
                 }
             } else {
-                if self.as_str() == prefix {
+                if self == prefix {
                     return Some(ConstructKind::Creating);
                 }
             }
         }
-        if CLONE_FUNC.contains(&self.as_str()) {
+        if CLONE_FUNC.contains(&self) {
             return Some(ConstructKind::Cloning);
         }
-        if MOVE_FUNC.contains(&self.as_str()) {
+        if MOVE_FUNC.contains(&self) {
             return Some(ConstructKind::Moving);
         }
 
@@ -120,15 +197,11 @@ impl FuncMatch for String {
     }
 
     fn is_replacement(&self) -> bool {
-        match self.as_str() {
-            "replaceAllUsesWith" => true,
-            "replaceUsesOfWith" => true,
-            _ => false,
-        }
+        matches!(self, "replaceAllUsesWith" | "replaceUsesOfWith")
     }
 
     fn is_debugloc_update(&self) -> Option<DLUpdateKind> {
-        match self.as_str() {
+        match self {
             "setDebugLoc" => Some(DLUpdateKind::Preserving),
             "applyMergedLocation" => Some(DLUpdateKind::Merging),
             "dropLocation" => Some(DLUpdateKind::Dropping),
@@ -138,7 +211,7 @@ impl FuncMatch for String {
     }
 
     fn is_insertion(&self) -> bool {
-        INSERT_FUNC.contains(&self.as_str())
+        INSERT_FUNC.contains(&self)
     }
 
     fn is_pass_entry(&self) -> bool {
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Kind of a hooked source site
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SiteKind {
+    Construct,
+    Replace,
+    Update,
+    Insert,
+}
+
+/// What the instrumenter saw at a hooked source site
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SiteInfo {
+    /// 1-based line, the same as the one passed to the runtime
+    pub site: usize,
+    pub kind: SiteKind,
+    /// The debug location destination, e.g., `NewI` of `OldI->replaceAllUsesWith(NewI)`,
+    /// which is empty for unnamed values such as a creation nested in an expression
+    pub dst: String,
+    /// The debug location sources, e.g., `OldI` of `OldI->replaceAllUsesWith(NewI)`
+    pub srcs: Vec<String>,
+}
+
+/// Instrumentation metadata of a pass, written next to the instrumented pass
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InstrumentMeta {
+    /// File name of the pass source, the same as the `Pass:` field of the runtime logs
+    pub pass: String,
+    /// SHA-256 of the pass source at instrumentation time
+    pub source_hash: String,
+    pub sites: Vec<SiteInfo>,
+}
+
+/// Hex SHA-256 of a pass source
+pub fn hash_source(code: &str) -> String {
+    Sha256::digest(code.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl InstrumentMeta {
+    pub fn new(pass: String) -> Self {
+        Self {
+            pass,
+            source_hash: String::new(),
+            sites: vec![],
+        }
+    }
+
+    /// Path of the metadata of `pass` in the instrumentation output directory
+    pub fn path_of(meta_dir: &Path, pass: &str) -> PathBuf {
+        meta_dir.join(format!("{}.disan.json", pass))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Malformed metadata {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Check that the pass source is the one instrumented, so that the sites still match
+    pub fn check_source(&self, code: &str) -> Result<(), String> {
+        if hash_source(code) == self.source_hash {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} has changed since the instrumentation, re-instrument it and rerun the pass",
+                self.pass
+            ))
+        }
+    }
+
+    pub fn add_site(&mut self, site: usize, kind: SiteKind, dst: String, srcs: Vec<String>) {
+        let info = SiteInfo {
+            site,
+            kind,
+            dst,
+            srcs,
+        };
+        if !self.sites.contains(&info) {
+            self.sites.push(info);
+        }
+    }
+
+    /// Return the sites of the `kind` at the 1-based line `site`
+    pub fn sites_at(&self, site: usize, kind: SiteKind) -> impl Iterator<Item = &SiteInfo> {
+        self.sites
+            .iter()
+            .filter(move |info| info.site == site && info.kind == kind)
+    }
+}
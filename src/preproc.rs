@@ -0,0 +1,470 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tree_sitter::Node;
+
+use crate::ast::AstNode;
+use crate::traverse::get_stmt_of;
+
+/// Macros whose arguments are compiled only into the debug builds
+pub const DEBUG_ONLY_MACROS: [&str; 2] = ["LLVM_DEBUG", "DEBUG_WITH_TYPE"];
+
+/// One entry of `compile_commands.json`, with either the command line or its arguments
+#[derive(Deserialize)]
+struct CompileCommand {
+    directory: PathBuf,
+    file: PathBuf,
+    command: Option<String>,
+    arguments: Option<Vec<String>>,
+}
+
+impl CompileCommand {
+    fn path(&self) -> PathBuf {
+        self.directory.join(&self.file)
+    }
+
+    fn arguments(&self) -> Vec<String> {
+        match (&self.arguments, &self.command) {
+            (Some(arguments), _) => arguments.clone(),
+            (None, Some(command)) => split_command(command),
+            (None, None) => vec![],
+        }
+    }
+}
+
+/// Split a command line into arguments, honoring the quotes and the escapes
+fn split_command(command: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', q) if q != Some('\'') => {
+                arg.extend(chars.next());
+                in_arg = true;
+            }
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            (c, _) => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+
+/// The compilation database of an LLVM build
+pub struct CompileDb {
+    commands: Vec<CompileCommand>,
+}
+
+impl CompileDb {
+    /// Load `compile_commands.json`, given either the file or the build directory holding it
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let path = if path.is_dir() {
+            path.join("compile_commands.json")
+        } else {
+            path.to_path_buf()
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let commands = serde_json::from_str(&content)
+            .map_err(|e| format!("Malformed compilation database {}: {}", path.display(), e))?;
+        Ok(Self { commands })
+    }
+
+    /// The defines of the command compiling the source, found by its path or its file name
+    pub fn defines_of(&self, source: &Path) -> Option<Defines> {
+        let source = source.canonicalize().ok()?;
+        let by_path = self.commands.iter().find(|command| {
+            command
+                .path()
+                .canonicalize()
+                .is_ok_and(|path| path == source)
+        });
+        let command = by_path.or_else(|| {
+            let mut by_name = self
+                .commands
+                .iter()
+                .filter(|command| command.file.file_name() == source.file_name());
+            by_name.next().filter(|_| by_name.next().is_none())
+        })?;
+        Some(Defines::from_arguments(&command.arguments()))
+    }
+}
+
+/// The macros defined (with their values) or undefined by a compile command
+pub struct Defines {
+    macros: HashMap<String, Option<String>>,
+}
+
+impl Defines {
+    fn from_arguments(arguments: &[String]) -> Self {
+        let mut macros = HashMap::new();
+        /* Assertions are switched by the build system alone, never by the headers */
+        macros.insert("NDEBUG".to_string(), None);
+
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            let (flag, rest) = match argument.as_str() {
+                "-D" | "-U" => (argument.as_str(), arguments.next().cloned()),
+                _ if argument.starts_with("-D") || argument.starts_with("-U") => {
+                    (&argument[..2], Some(argument[2..].to_string()))
+                }
+                _ => continue,
+            };
+            let Some(rest) = rest else {
+                continue;
+            };
+            if flag == "-U" {
+                macros.insert(rest, None);
+                continue;
+            }
+            match rest.split_once('=') {
+                Some((name, value)) => macros.insert(name.to_string(), Some(value.to_string())),
+                None => macros.insert(rest, Some("1".to_string())),
+            };
+        }
+        Self { macros }
+    }
+
+    /// Whether the macro is defined, or None if the build does not decide it
+    fn is_defined(&self, name: &str) -> Option<bool> {
+        self.macros.get(name).map(|value| value.is_some())
+    }
+
+    fn value(&self, name: &str) -> Option<i64> {
+        match self.macros.get(name)? {
+            Some(value) => parse_number(value),
+            None => Some(0),
+        }
+    }
+}
+
+fn parse_number(literal: &str) -> Option<i64> {
+    let literal = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+    match literal.strip_prefix("0x").or(literal.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => literal.parse().ok(),
+    }
+}
+
+/// Evaluate a `#if` condition, or None if it depends on the macros unknown to the build
+fn eval(node: &Node, code: &str, defines: &Defines) -> Option<i64> {
+    let operand = |field: &str| eval(&node.child_by_field_name(field)?, code, defines);
+    match node.kind() {
+        "number_literal" => parse_number(&node.to_source(code)),
+        "identifier" => defines.value(&node.to_source(code)),
+        "preproc_defined" => {
+            let name = node.named_child(0)?.to_source(code);
+            defines.is_defined(&name).map(i64::from)
+        }
+        "parenthesized_expression" => eval(&node.named_child(0)?, code, defines),
+        "unary_expression" => {
+            let argument = operand("argument")?;
+            match node.child_by_field_name("operator")?.kind() {
+                "!" => Some(i64::from(argument == 0)),
+                "-" => Some(argument.wrapping_neg()),
+                "~" => Some(!argument),
+                _ => None,
+            }
+        }
+        "binary_expression" => {
+            let operator = node.child_by_field_name("operator")?.kind();
+            /* Short-circuit, as the preprocessor does, to decide more conditions */
+            match operator {
+                "&&" => {
+                    return match (operand("left"), operand("right")) {
+                        (Some(0), _) | (_, Some(0)) => Some(0),
+                        (Some(_), Some(_)) => Some(1),
+                        _ => None,
+                    }
+                }
+                "||" => {
+                    return match (operand("left"), operand("right")) {
+                        (Some(l), _) if l != 0 => Some(1),
+                        (_, Some(r)) if r != 0 => Some(1),
+                        (Some(_), Some(_)) => Some(0),
+                        _ => None,
+                    }
+                }
+                _ => {}
+            }
+            let (left, right) = (operand("left")?, operand("right")?);
+            Some(match operator {
+                "==" => i64::from(left == right),
+                "!=" => i64::from(left != right),
+                "<" => i64::from(left < right),
+                "<=" => i64::from(left <= right),
+                ">" => i64::from(left > right),
+                ">=" => i64::from(left >= right),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" if right != 0 => left / right,
+                "%" if right != 0 => left % right,
+                "&" => left & right,
+                "|" => left | right,
+                "^" => left ^ right,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                _ => return None,
+            })
+        }
+        /* E.g., `__has_feature(..)` and the other builtins */
+        _ => None,
+    }
+}
+
+/// Whether the branch of a conditional directive is taken by its own condition
+fn condition_of(directive: &Node, code: &str, defines: &Defines) -> Option<bool> {
+    match directive.kind() {
+        "preproc_ifdef" | "preproc_elifdef" => {
+            let name = directive.child_by_field_name("name")?.to_source(code);
+            let defined = defines.is_defined(&name)?;
+            let negated = matches!(directive.child(0)?.kind(), "#ifndef" | "#elifndef");
+            Some(defined != negated)
+        }
+        _ => eval(&directive.child_by_field_name("condition")?, code, defines).map(|v| v != 0),
+    }
+}
+
+fn and(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+/// Whether a branch is taken, i.e., its condition holds and the preceding ones do not
+fn branch_taken(branch: &Node, code: &str, defines: &Defines) -> Option<bool> {
+    let mut taken = match branch.kind() {
+        "preproc_else" => Some(true),
+        _ => condition_of(branch, code, defines),
+    };
+    let mut current = *branch;
+    while let Some(previous) = current.parent().filter(|parent| {
+        parent
+            .child_by_field_name("alternative")
+            .is_some_and(|alternative| alternative.id() == current.id())
+    }) {
+        taken = and(taken, condition_of(&previous, code, defines).map(|c| !c));
+        current = previous;
+    }
+    taken
+}
+
+/// Whether the identifier names a macro by the LLVM conventions, i.e., in upper case
+fn is_macro_name(name: &str) -> bool {
+    name == "assert"
+        || (name.chars().any(|c| c.is_ascii_uppercase())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+}
+
+/// The macro invoked by the call, if it looks like a macro invocation
+fn macro_of(call: &Node, code: &str) -> Option<String> {
+    let callee = call.child_by_field_name("function")?;
+    let name = callee.to_source(code);
+    (callee.kind() == "identifier" && is_macro_name(&name)).then_some(name)
+}
+
+/// The innermost macro whose arguments hold the site, e.g., `LLVM_DEBUG` or `assert`
+pub fn enclosing_macro(site: &Node, code: &str) -> Option<String> {
+    let mut parent = site.parent();
+    while let Some(node) = parent {
+        if node.kind() == "call_expression" {
+            if let Some(name) = macro_of(&node, code) {
+                return Some(name);
+            }
+        }
+        parent = node.parent();
+    }
+    None
+}
+
+/// Whether preprocessor directives split the statement holding the site
+pub fn crosses_directive(site: &Node) -> bool {
+    fn has_directive(node: &Node) -> bool {
+        let mut cursor = node.walk();
+        let has = node
+            .children(&mut cursor)
+            .any(|child| child.kind().starts_with("preproc_") || has_directive(&child));
+        has
+    }
+    let stmt = get_stmt_of(site);
+    stmt.has_error() || has_directive(&stmt)
+}
+
+/// Where a site lies with respect to the build
+#[derive(Debug, PartialEq, Eq)]
+pub enum Region {
+    /// Compiled, as far as the build decides
+    Built,
+    /// In a conditional branch not taken by the build
+    NotBuilt,
+    /// In a conditional branch depending on the macros unknown to the build
+    Undetermined,
+    /// In the arguments of a debug-only macro
+    DebugOnly(String),
+}
+
+/// Locate the site among the conditional branches and the debug-only macros
+pub fn region_of(node: &Node, code: &str, defines: &Defines) -> Region {
+    let mut taken = Some(true);
+    let mut child = *node;
+    while let Some(parent) = child.parent() {
+        match parent.kind() {
+            "preproc_if" | "preproc_ifdef" | "preproc_elif" | "preproc_elifdef"
+            | "preproc_else" => {
+                /* Skip the chain of the branches following the one of the site */
+                let in_alternative = parent
+                    .child_by_field_name("alternative")
+                    .is_some_and(|alternative| alternative.id() == child.id());
+                if !in_alternative {
+                    taken = and(taken, branch_taken(&parent, code, defines));
+                }
+            }
+            "call_expression" => {
+                if let Some(name) = macro_of(&parent, code) {
+                    if DEBUG_ONLY_MACROS.contains(&name.as_str()) {
+                        return Region::DebugOnly(name);
+                    }
+                }
+            }
+            _ => {}
+        }
+        child = parent;
+    }
+    match taken {
+        Some(true) => Region::Built,
+        Some(false) => Region::NotBuilt,
+        None => Region::Undetermined,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traverse::get_children_of_kind;
+    use tree_sitter::{Parser, Tree};
+
+    fn parse(code: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_cpp::language()).unwrap();
+        parser.parse(code, None).unwrap()
+    }
+
+    fn defines(command: &str) -> Defines {
+        Defines::from_arguments(&split_command(command))
+    }
+
+    /// The condition of the first `#if` in the code
+    fn condition(code: &str, defines: &Defines) -> Option<i64> {
+        let tree = parse(code);
+        let directive = get_children_of_kind(&tree.root_node(), "preproc_if")[0];
+        eval(
+            &directive.child_by_field_name("condition").unwrap(),
+            code,
+            defines,
+        )
+    }
+
+    /// The regions of the calls in the code, in order
+    fn regions(code: &str, defines: &Defines) -> Vec<Region> {
+        let tree = parse(code);
+        get_children_of_kind(&tree.root_node(), "call_expression")
+            .iter()
+            .map(|call| region_of(call, code, defines))
+            .collect()
+    }
+
+    #[test]
+    fn split_quoted_command() {
+        assert_eq!(
+            split_command(r#"clang++ -DA="x y" '-DB=\n' -DC=\"z\"  -c a.cpp"#),
+            ["clang++", "-DA=x y", r"-DB=\n", r#"-DC="z""#, "-c", "a.cpp"]
+        );
+    }
+
+    #[test]
+    fn eval_defined_and_value() {
+        let code = "#if defined(X) && X >= 2\n#endif\n";
+        assert_eq!(condition(code, &defines("cc -DX=2")), Some(1));
+        assert_eq!(condition(code, &defines("cc -D X=0x1")), Some(0));
+        assert_eq!(condition(code, &defines("cc -UX")), Some(0));
+        /* Left to the headers */
+        assert_eq!(condition(code, &defines("cc")), None);
+    }
+
+    #[test]
+    fn eval_short_circuits_unknown_macros() {
+        let code = "#if !defined(NDEBUG) || __has_feature(x)\n#endif\n";
+        assert_eq!(condition(code, &defines("cc")), Some(1));
+        assert_eq!(condition(code, &defines("cc -DNDEBUG")), None);
+    }
+
+    #[test]
+    fn elif_chain() {
+        let code = "#if X == 1\na();\n#elif X == 2\nb();\n#elif Y\nc();\n#else\nd();\n#endif\n";
+        assert_eq!(
+            regions(code, &defines("cc -DX=2")),
+            [
+                Region::NotBuilt,
+                Region::Built,
+                Region::NotBuilt,
+                Region::NotBuilt
+            ]
+        );
+        assert_eq!(
+            regions(code, &defines("cc -DX=3 -DY")),
+            [
+                Region::NotBuilt,
+                Region::NotBuilt,
+                Region::Built,
+                Region::NotBuilt
+            ]
+        );
+        assert_eq!(
+            regions(code, &defines("cc -DX=3")),
+            [
+                Region::NotBuilt,
+                Region::NotBuilt,
+                Region::Undetermined,
+                Region::Undetermined
+            ]
+        );
+    }
+
+    #[test]
+    fn ifndef_and_debug_only() {
+        let code = "#ifndef NDEBUG\na();\n#endif\nLLVM_DEBUG(b());\n";
+        assert_eq!(
+            regions(code, &defines("cc -DNDEBUG"))[..1],
+            [Region::NotBuilt]
+        );
+        assert_eq!(regions(code, &defines("cc"))[0], Region::Built);
+        assert_eq!(
+            regions(code, &defines("cc"))[2],
+            Region::DebugOnly("LLVM_DEBUG".to_string())
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use colored::{ColoredString, Colorize};
+
+use crate::log::{IrContext, IrValue, LogRecord, Verdict};
+use crate::source::{PassSource, SourceRoot};
+
+/// Deduplicate the records and sort them by their construct sites. Records
+/// differing only in the module or function checked are the same violation.
+pub fn collect_records(mut records: Vec<LogRecord>) -> Vec<LogRecord> {
+    records.sort_by_key(|record| (record.construct_site, record.pass.clone(), record.body()));
+    records.dedup_by_key(|record| record.to_string());
+    records
+}
+
+fn verdict_tag(verdict: Verdict) -> ColoredString {
+    match verdict {
+        Verdict::Pass => verdict.log_name().green().bold(),
+        Verdict::Warn => verdict.log_name().yellow().bold(),
+        Verdict::Fail => verdict.log_name().red().bold(),
+    }
+}
+
+/// Describe the violation of a record, e.g., `expected Preserve, found Drop`
+pub fn violation_message(record: &LogRecord) -> String {
+    match &record.update {
+        Some((_, update_kind)) => format!(
+            "expected {}, found {}",
+            record.expected.log_name(),
+            update_kind.log_name()
+        ),
+        None => format!("expected {}, found no update", record.expected.log_name()),
+    }
+}
+
+/// The replace, insert and update sites of a record with their labels
+pub fn related_sites(record: &LogRecord) -> Vec<(usize, String)> {
+    let mut sites: Vec<(usize, String)> = record
+        .replace_sites
+        .iter()
+        .map(|&site| (site, "replaced here".to_string()))
+        .collect();
+    if let Some(insert_site) = record.insert_site {
+        sites.push((insert_site, "inserted here".to_string()));
+    }
+    if let Some((update_site, update_kind)) = &record.update {
+        sites.push((
+            *update_site,
+            format!("updated here ({})", update_kind.log_name()),
+        ));
+    }
+    sites
+}
+
+/// All the sites of a record with their labels, in the order of lines
+pub fn labeled_sites(record: &LogRecord) -> Vec<(usize, String)> {
+    let mut sites = vec![(
+        record.construct_site,
+        format!("constructed here ({})", record.construct_kind.log_name()),
+    )];
+    sites.extend(related_sites(record));
+    sites.sort_by_key(|(site, _)| *site);
+    sites
+}
+
+/// Print a source line with carets under its code and a label after them
+fn print_annotated_line(source: &PassSource, site: usize, label: &str, gutter: usize) {
+    let Some(line) = source.line(site) else {
+        println!(
+            "{:>gutter$} {} {}",
+            site.to_string().blue().bold(),
+            "|".blue().bold(),
+            format!("<no such line> {}", label).dimmed(),
+        );
+        return;
+    };
+
+    let indent = line.len() - line.trim_start().len();
+    let width = line.trim().chars().count().max(1);
+    println!(
+        "{:>gutter$} {} {}",
+        site.to_string().blue().bold(),
+        "|".blue().bold(),
+        line
+    );
+    println!(
+        "{:>gutter$} {} {}{} {}",
+        "",
+        "|".blue().bold(),
+        &line[..indent],
+        "^".repeat(width).red().bold(),
+        label.red().bold(),
+    );
+}
+
+/// Print a violation like a compiler diagnostic, with the construct, replace
+/// and update sites resolved against the pass source
+pub fn print_diagnostic(record: &LogRecord, source: &PassSource) {
+    let severity = match record.verdict {
+        Verdict::Fail => "error".red().bold(),
+        _ => "warning".yellow().bold(),
+    };
+    println!("{}: {}", severity, violation_message(record).bold());
+    println!(
+        "  {} {}:{}",
+        "-->".blue().bold(),
+        source.path.display(),
+        record.construct_site
+    );
+
+    let sites = labeled_sites(record);
+
+    let gutter = sites
+        .iter()
+        .map(|(site, _)| site.to_string().len())
+        .max()
+        .unwrap();
+    println!("{:>gutter$} {}", "", "|".blue().bold());
+    for (site, label) in &sites {
+        print_annotated_line(source, *site, label, gutter);
+    }
+    if let (Some(module), Some(function)) = (&record.module, &record.function) {
+        println!(
+            "{:>gutter$} {} {}: checked in function `{}` of module `{}`",
+            "",
+            "=".blue().bold(),
+            "note".bold(),
+            function,
+            module,
+        );
+    }
+    if let Some(context) = &record.context {
+        print_ir_context(context, gutter);
+    }
+    println!(
+        "{:>gutter$} {} {}: the proper update is {}\n",
+        "",
+        "=".blue().bold(),
+        "note".bold(),
+        record.expected,
+    );
+}
+
+/// A one-line description of an IR value, e.g., `NewI at a.c:3:5`
+pub fn describe_ir_value(value: &IrValue) -> String {
+    let name = if value.name.is_empty() {
+        "<unnamed>"
+    } else {
+        &value.name
+    };
+    match &value.loc {
+        Some(loc) => format!("`{}` at {}", name, loc),
+        None => format!("`{}` without location", name),
+    }
+}
+
+fn print_ir_context(context: &IrContext, gutter: usize) {
+    let note = |message: String| {
+        println!(
+            "{:>gutter$} {} {}: {}",
+            "",
+            "=".blue().bold(),
+            "note".bold(),
+            message
+        )
+    };
+    if let Some(dst) = &context.dst {
+        note(format!(
+            "destination {} in IR function `{}`:\n{:>gutter$}       {}",
+            describe_ir_value(dst),
+            context.function,
+            "",
+            dst.ir
+        ));
+    }
+    for src in &context.srcs {
+        let site = src
+            .site
+            .map_or(String::new(), |site| format!(" (line {})", site));
+        note(format!(
+            "source {}{}:\n{:>gutter$}       {}",
+            describe_ir_value(src),
+            site,
+            "",
+            src.ir
+        ));
+    }
+}
+
+/// Print the pass, warn and fail groups of the records followed by their counts.
+/// The violations are annotated with the pass sources if a source root is given.
+pub fn print_report(records: &[LogRecord], mut source_root: Option<&mut SourceRoot>) {
+    let verdicts = [Verdict::Pass, Verdict::Warn, Verdict::Fail];
+    for verdict in verdicts {
+        for record in records.iter().filter(|record| record.verdict == verdict) {
+            let source = match (verdict, source_root.as_deref_mut()) {
+                (Verdict::Pass, _) | (_, None) => None,
+                (_, Some(source_root)) => source_root.get(&record.pass),
+            };
+            match source {
+                Some(source) => print_diagnostic(record, source),
+                None => println!("[{}] {}", verdict_tag(verdict), record.body()),
+            }
+        }
+    }
+
+    let counts: Vec<String> = verdicts
+        .iter()
+        .map(|&verdict| {
+            let count = records.iter().filter(|r| r.verdict == verdict).count();
+            format!("{}: {}", verdict_tag(verdict), count)
+        })
+        .collect();
+    println!("\n{}", counts.join(", "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sites_in_line_order() {
+        let record: LogRecord =
+            "fail: Merge [Construct: 30, Clone; Replace: 12, 40; Update: 20, Drop; Pass: Foo.cpp]"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            labeled_sites(&record),
+            [
+                (12, "replaced here".to_string()),
+                (20, "updated here (Drop)".to_string()),
+                (30, "constructed here (Clone)".to_string()),
+                (40, "replaced here".to_string()),
+            ]
+        );
+        assert_eq!(violation_message(&record), "expected Merge, found Drop");
+    }
+
+    #[test]
+    fn dedup_records() {
+        let records: Vec<LogRecord> = [
+            "fail: Preserve [Construct: 20, Create; Pass: Foo.cpp]",
+            "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+            "fail: Preserve [Construct: 20, Create; Pass: Foo.cpp]",
+        ]
+        .iter()
+        .map(|line| line.parse().unwrap())
+        .collect();
+        let sites: Vec<usize> = collect_records(records)
+            .iter()
+            .map(|record| record.construct_site)
+            .collect();
+        assert_eq!(sites, [10, 20]);
+    }
+}
@@ -0,0 +1,335 @@
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hook::RUNTIME_ABI_VERSION;
+
+/// The runtime library, embedded so that the binary alone can install it
+const RUNTIME_HEADER: &str = include_str!("../library/RuntimeChecker.h");
+const RUNTIME_SOURCE: &str = include_str!("../library/RuntimeChecker.cpp");
+
+/// The entry of the runtime source in the source list of `LLVMTransformUtils`
+const SOURCE_ENTRY: &str = "RuntimeChecker.cpp";
+const UTILS_LIBRARY: &str = "add_llvm_component_library(LLVMTransformUtils";
+
+/// The defaults of the log destination in the runtime header, see [`RuntimeConfig`]
+const DEFAULT_LOG_DIR: &str = "#define DISAN_LOG_DIR \"/tmp/disan/\"";
+const DEFAULT_LOG_PER_PROCESS: &str = "#define DISAN_LOG_PER_PROCESS 0";
+
+/// The log destination baked into the installed runtime, which the runtime
+/// still lets `DISAN_LOG_DIR` and `DISAN_LOG_PER_PROCESS` override
+pub struct RuntimeConfig {
+    /// The log directory, or `-` for stderr, instead of `/tmp/disan/`
+    pub log_dir: Option<String>,
+    /// Name the logs by process id as well
+    pub per_process: bool,
+}
+
+impl RuntimeConfig {
+    /// The runtime header with the log destination filled in
+    fn header(&self) -> Result<String, String> {
+        let mut header = RUNTIME_HEADER.to_string();
+        if let Some(log_dir) = &self.log_dir {
+            let log_dir = if log_dir == "-" {
+                log_dir.clone()
+            } else {
+                /* The passes run in the working directories of lit */
+                let path = std::path::absolute(log_dir)
+                    .map_err(|e| format!("Invalid log directory {}: {}", log_dir, e))?;
+                path.to_str()
+                    .ok_or(format!("Non UTF-8 log directory: {}", path.display()))?
+                    .to_string()
+            };
+            let define = format!(
+                "#define DISAN_LOG_DIR \"{}\"",
+                log_dir.replace('\\', "\\\\").replace('"', "\\\"")
+            );
+            header = header.replacen(DEFAULT_LOG_DIR, &define, 1);
+        }
+        if self.per_process {
+            header = header.replacen(
+                DEFAULT_LOG_PER_PROCESS,
+                "#define DISAN_LOG_PER_PROCESS 1",
+                1,
+            );
+        }
+        Ok(header)
+    }
+}
+
+/// The directories of an LLVM project the runtime library is installed into
+pub struct LlvmTree {
+    include_dir: PathBuf,
+    lib_dir: PathBuf,
+}
+
+impl LlvmTree {
+    pub fn open(llvm_root: &Path) -> Result<Self, String> {
+        let tree = Self {
+            include_dir: llvm_root.join("llvm/include/llvm/Transforms/Utils"),
+            lib_dir: llvm_root.join("llvm/lib/Transforms/Utils"),
+        };
+        for dir in [&tree.include_dir, &tree.lib_dir] {
+            if !dir.is_dir() {
+                return Err(format!(
+                    "Cannot find {} under {}, is it the root of the LLVM project?",
+                    dir.display(),
+                    llvm_root.display()
+                ));
+            }
+        }
+        if !tree.cmake_path().is_file() {
+            return Err(format!("Cannot find {}", tree.cmake_path().display()));
+        }
+        Ok(tree)
+    }
+
+    pub fn header_path(&self) -> PathBuf {
+        self.include_dir.join("RuntimeChecker.h")
+    }
+
+    pub fn source_path(&self) -> PathBuf {
+        self.lib_dir.join(SOURCE_ENTRY)
+    }
+
+    pub fn cmake_path(&self) -> PathBuf {
+        self.lib_dir.join("CMakeLists.txt")
+    }
+}
+
+fn is_source_entry(line: &str) -> bool {
+    line.trim() == SOURCE_ENTRY
+}
+
+/// Add the runtime source to the source list of `LLVMTransformUtils`, keeping
+/// the list sorted. Return None if it is already there.
+fn add_to_cmake(cmake: &str) -> Result<Option<String>, String> {
+    if cmake.lines().any(is_source_entry) {
+        return Ok(None);
+    }
+    let lines: Vec<&str> = cmake.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with(UTILS_LIBRARY))
+        .ok_or(format!("No `{}` in the CMakeLists.txt", UTILS_LIBRARY))?;
+
+    /* The sources are listed one per line until a blank line or a keyword */
+    let entries = lines[start + 1..]
+        .iter()
+        .take_while(|line| line.trim().ends_with(".cpp"))
+        .count();
+    let sources = &lines[start + 1..start + 1 + entries];
+    let pos = sources
+        .iter()
+        .position(|line| line.trim().to_lowercase() > SOURCE_ENTRY.to_lowercase())
+        .unwrap_or(entries);
+    let indent = sources
+        .first()
+        .map_or("  ", |line| &line[..line.len() - line.trim_start().len()]);
+
+    let mut patched: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    patched.insert(start + 1 + pos, format!("{}{}", indent, SOURCE_ENTRY));
+    Ok(Some(patched.join("\n") + "\n"))
+}
+
+/// Remove the runtime source from the CMakeLists.txt. Return None if it is not there.
+fn remove_from_cmake(cmake: &str) -> Option<String> {
+    if !cmake.lines().any(is_source_entry) {
+        return None;
+    }
+    let kept: Vec<&str> = cmake
+        .lines()
+        .filter(|line| !is_source_entry(line))
+        .collect();
+    Some(kept.join("\n") + "\n")
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), String> {
+    if fs::read_to_string(path).is_ok_and(|old| old == content) {
+        println!("Up to date: {}", path.display());
+        return Ok(());
+    }
+    fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    println!("Wrote: {}", path.display());
+    Ok(())
+}
+
+/// The `DISAN_RUNTIME_ABI` defined in a runtime header, if any
+fn abi_of(header: &str) -> Option<u32> {
+    header.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("#define DISAN_RUNTIME_ABI")?
+            .trim()
+            .parse()
+            .ok()
+    })
+}
+
+/// Check that the runtime installed in the LLVM project is built and provides
+/// the hooks of [`RUNTIME_ABI_VERSION`], before instrumenting passes against it
+pub fn check_installed(tree: &LlvmTree) -> Result<(), String> {
+    let header_path = tree.header_path();
+    let reinstall = "run `disan install-runtime` first";
+    let header = fs::read_to_string(&header_path).map_err(|_| {
+        format!(
+            "No runtime library installed at {}, {}",
+            header_path.display(),
+            reinstall
+        )
+    })?;
+    match abi_of(&header) {
+        Some(RUNTIME_ABI_VERSION) => {}
+        Some(abi) => {
+            return Err(format!(
+                "The runtime library installed at {} has ABI version {} but DISan expects {}, {}",
+                header_path.display(),
+                abi,
+                RUNTIME_ABI_VERSION,
+                reinstall
+            ))
+        }
+        None => {
+            return Err(format!(
+                "The runtime library installed at {} predates the ABI versioning, {}",
+                header_path.display(),
+                reinstall
+            ))
+        }
+    }
+
+    let cmake_path = tree.cmake_path();
+    let cmake = fs::read_to_string(&cmake_path)
+        .map_err(|e| format!("Failed to read {}: {}", cmake_path.display(), e))?;
+    if !tree.source_path().is_file() || !cmake.lines().any(is_source_entry) {
+        return Err(format!(
+            "The runtime library is not built into LLVMTransformUtils, {}",
+            reinstall
+        ));
+    }
+    Ok(())
+}
+
+/// Copy the runtime library into the LLVM project and build it into
+/// `LLVMTransformUtils`. Running it again updates the copies.
+pub fn install(tree: &LlvmTree, config: &RuntimeConfig) -> Result<(), String> {
+    write_file(&tree.header_path(), &config.header()?)?;
+    write_file(&tree.source_path(), RUNTIME_SOURCE)?;
+
+    let cmake_path = tree.cmake_path();
+    let cmake = fs::read_to_string(&cmake_path)
+        .map_err(|e| format!("Failed to read {}: {}", cmake_path.display(), e))?;
+    match add_to_cmake(&cmake)? {
+        Some(patched) => {
+            fs::write(&cmake_path, patched)
+                .map_err(|e| format!("Failed to write {}: {}", cmake_path.display(), e))?;
+            println!("Patched: {}", cmake_path.display());
+        }
+        None => println!("Up to date: {}", cmake_path.display()),
+    }
+
+    println!("{}", "Installed the runtime library!".green().bold());
+    Ok(())
+}
+
+/// Revert [`install`], removing the copies and the CMakeLists.txt entry
+pub fn uninstall(tree: &LlvmTree) -> Result<(), String> {
+    let cmake_path = tree.cmake_path();
+    let cmake = fs::read_to_string(&cmake_path)
+        .map_err(|e| format!("Failed to read {}: {}", cmake_path.display(), e))?;
+    if let Some(reverted) = remove_from_cmake(&cmake) {
+        fs::write(&cmake_path, reverted)
+            .map_err(|e| format!("Failed to write {}: {}", cmake_path.display(), e))?;
+        println!("Reverted: {}", cmake_path.display());
+    }
+
+    for path in [tree.header_path(), tree.source_path()] {
+        if path.exists() {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+            println!("Removed: {}", path.display());
+        }
+    }
+
+    println!("{}", "Uninstalled the runtime library!".green().bold());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CMAKE: &str = "\
+add_llvm_component_library(LLVMTransformUtils
+  AddDiscriminators.cpp
+  Local.cpp
+  SCCPSolver.cpp
+
+  ADDITIONAL_HEADER_DIRS
+  ${LLVM_MAIN_INCLUDE_DIR}/llvm/Transforms
+  )
+";
+
+    #[test]
+    fn add_keeps_sources_sorted() {
+        let patched = add_to_cmake(CMAKE).unwrap().unwrap();
+        assert_eq!(
+            patched,
+            CMAKE.replace("  Local.cpp\n", "  Local.cpp\n  RuntimeChecker.cpp\n")
+        );
+    }
+
+    #[test]
+    fn add_twice_is_a_no_op() {
+        let patched = add_to_cmake(CMAKE).unwrap().unwrap();
+        assert_eq!(add_to_cmake(&patched), Ok(None));
+    }
+
+    #[test]
+    fn remove_restores_the_original() {
+        let patched = add_to_cmake(CMAKE).unwrap().unwrap();
+        assert_eq!(remove_from_cmake(&patched).as_deref(), Some(CMAKE));
+        assert_eq!(remove_from_cmake(CMAKE), None);
+    }
+
+    #[test]
+    fn add_without_the_library() {
+        assert!(add_to_cmake("add_llvm_component_library(LLVMCore\n  Core.cpp\n  )\n").is_err());
+    }
+
+    #[test]
+    fn default_log_destination() {
+        let config = RuntimeConfig {
+            log_dir: None,
+            per_process: false,
+        };
+        assert_eq!(config.header(), Ok(RUNTIME_HEADER.to_string()));
+    }
+
+    #[test]
+    fn configured_log_destination() {
+        let config = RuntimeConfig {
+            log_dir: Some("/tmp/a \"b\"".to_string()),
+            per_process: true,
+        };
+        let header = config.header().unwrap();
+        assert!(header.contains(r#"#define DISAN_LOG_DIR "/tmp/a \"b\"""#));
+        assert!(header.contains("#define DISAN_LOG_PER_PROCESS 1"));
+        assert!(!header.contains(DEFAULT_LOG_DIR));
+
+        let stderr = RuntimeConfig {
+            log_dir: Some("-".to_string()),
+            per_process: false,
+        };
+        assert!(stderr
+            .header()
+            .unwrap()
+            .contains("#define DISAN_LOG_DIR \"-\""));
+    }
+
+    #[test]
+    fn abi_of_the_embedded_header() {
+        assert_eq!(abi_of(RUNTIME_HEADER), Some(RUNTIME_ABI_VERSION));
+        assert_eq!(abi_of("#define DISAN_RUNTIME_ABI 2\n"), Some(2));
+        assert_eq!(abi_of("#define DISAN_LOG_SCHEMA 1\n"), None);
+    }
+}
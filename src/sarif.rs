@@ -0,0 +1,203 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::log::{LogRecord, Verdict};
+use crate::matcher::DLUpdateKind;
+use crate::report::{related_sites, violation_message};
+use crate::source::SourceRoot;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Rule ID of a violation, derived from the expected and actual update kinds,
+/// e.g., `DISAN-MISSING-PRESERVE` or `DISAN-WRONG-DROP`
+pub fn rule_id(record: &LogRecord) -> String {
+    match (record.expected, &record.update) {
+        (DLUpdateKind::Others, _) => "DISAN-UNTRACKED-REPLACEMENT".to_string(),
+        (expected, None) => format!("DISAN-MISSING-{}", expected.log_name().to_uppercase()),
+        (_, Some((_, actual))) => format!("DISAN-WRONG-{}", actual.log_name().to_uppercase()),
+    }
+}
+
+fn rule_description(record: &LogRecord) -> String {
+    match (record.expected, &record.update) {
+        (DLUpdateKind::Others, _) => {
+            "An untracked instruction is involved in a replacement".to_string()
+        }
+        (expected, None) => format!(
+            "Missing debug location update, which should be {}",
+            expected.log_name()
+        ),
+        (_, Some((_, actual))) => format!("Improper debug location update {}", actual.log_name()),
+    }
+}
+
+fn location(uri: &str, base: Option<&str>, site: usize, snippet: Option<&str>) -> Value {
+    let mut artifact = json!({ "uri": uri });
+    if let Some(base) = base {
+        artifact["uriBaseId"] = json!(base);
+    }
+    let mut region = json!({ "startLine": site });
+    if let Some(snippet) = snippet {
+        region["snippet"] = json!({ "text": snippet });
+    }
+    json!({
+        "physicalLocation": {
+            "artifactLocation": artifact,
+            "region": region,
+        }
+    })
+}
+
+/// Convert the fail and warn records to a SARIF log. The pass sources found
+/// under the source root are referred to relative to it (`%SRCROOT%`),
+/// while the others are referred to by their file names.
+pub fn to_sarif(records: &[LogRecord], mut source_root: Option<&mut SourceRoot>) -> Value {
+    let violations: Vec<&LogRecord> = records
+        .iter()
+        .filter(|record| record.verdict != Verdict::Pass)
+        .collect();
+
+    let mut rules: BTreeMap<String, String> = BTreeMap::new();
+    for record in &violations {
+        rules.insert(rule_id(record), rule_description(record));
+    }
+    let rule_index: BTreeMap<&String, usize> = rules
+        .keys()
+        .enumerate()
+        .map(|(idx, id)| (id, idx))
+        .collect();
+
+    let mut results = vec![];
+    for record in violations {
+        let source = source_root.as_deref_mut().and_then(|source_root| {
+            let source = source_root.get(&record.pass)?.clone();
+            Some((source_root.relative_path(&source), source))
+        });
+        let (uri, base) = match &source {
+            Some((relative, _)) => (relative.as_str(), Some("SRCROOT")),
+            None => (record.pass.as_str(), None),
+        };
+        let snippet = |site: usize| {
+            source
+                .as_ref()
+                .and_then(|(_, source)| source.line(site))
+                .map(str::trim)
+        };
+
+        let mut related = vec![];
+        for (id, (site, label)) in related_sites(record).into_iter().enumerate() {
+            let mut related_location = location(uri, base, site, snippet(site));
+            related_location["id"] = json!(id);
+            related_location["message"] = json!({ "text": label });
+            related.push(related_location);
+        }
+
+        let id = rule_id(record);
+        results.push(json!({
+            "ruleId": id,
+            "ruleIndex": rule_index[&id],
+            "level": match record.verdict {
+                Verdict::Fail => "error",
+                _ => "warning",
+            },
+            "message": {
+                "text": format!(
+                    "{} for the instruction constructed here ({}), the proper update is {}",
+                    violation_message(record),
+                    record.construct_kind.log_name(),
+                    record.expected,
+                ),
+            },
+            "locations": [location(uri, base, record.construct_site, snippet(record.construct_site))],
+            "relatedLocations": related,
+        }));
+    }
+
+    let mut run = json!({
+        "tool": {
+            "driver": {
+                "name": "DISan",
+                "version": env!("CARGO_PKG_VERSION"),
+                "rules": rules
+                    .iter()
+                    .map(|(id, description)| json!({
+                        "id": id,
+                        "shortDescription": { "text": description },
+                    }))
+                    .collect::<Vec<_>>(),
+            }
+        },
+        "results": results,
+    });
+    if let Some(source_root) = source_root {
+        run["originalUriBaseIds"] = json!({
+            "SRCROOT": { "uri": source_root.root_uri() },
+        });
+    }
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [run],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<LogRecord> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn rule_ids() {
+        let ids: Vec<String> = records(&[
+            "fail: Preserve [Construct: 10, Create; Pass: Foo.cpp]",
+            "fail: Merge [Construct: 10, Create; Update: 12, Drop; Pass: Foo.cpp]",
+            "warn: Any [Construct: 10, Create; Pass: Foo.cpp]",
+        ])
+        .iter()
+        .map(rule_id)
+        .collect();
+        assert_eq!(
+            ids,
+            [
+                "DISAN-MISSING-PRESERVE",
+                "DISAN-WRONG-DROP",
+                "DISAN-UNTRACKED-REPLACEMENT"
+            ]
+        );
+    }
+
+    #[test]
+    fn results_of_violations() {
+        let sarif = to_sarif(
+            &records(&[
+                "fail: Preserve [Construct: 10, Create; Replace: 11, 12; Pass: Foo.cpp]",
+                "warn: Drop [Construct: 20, Move; Update: 21, Preserve; Pass: Foo.cpp]",
+                "pass: Preserve [Construct: 30, Create; Update: 31, Preserve; Pass: Foo.cpp]",
+            ]),
+            None,
+        );
+        let run = &sarif["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), 2);
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["level"], "error");
+        assert_eq!(results[1]["level"], "warning");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "Foo.cpp");
+        assert_eq!(location["region"]["startLine"], 10);
+        let related = results[0]["relatedLocations"].as_array().unwrap();
+        assert_eq!(related.len(), 2);
+        assert_eq!(related[1]["message"]["text"], "replaced here");
+        for result in results {
+            let index = result["ruleIndex"].as_u64().unwrap() as usize;
+            assert_eq!(rules[index]["id"], result["ruleId"]);
+        }
+        assert!(run.get("originalUriBaseIds").is_none());
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::hash_source;
+
+/// The original source of an instrumented pass, used to resolve the source
+/// sites recorded in the runtime logs
+#[derive(Clone)]
+pub struct PassSource {
+    pub path: PathBuf,
+    pub code: String,
+}
+
+impl PassSource {
+    pub fn load(path: &Path) -> Option<Self> {
+        let code = fs::read_to_string(path).ok()?;
+        Some(Self {
+            path: path.to_path_buf(),
+            code,
+        })
+    }
+
+    /// Return the line at the 1-based `site`, as recorded by the instrumenter
+    pub fn line(&self, site: usize) -> Option<&str> {
+        self.code.lines().nth(site.checked_sub(1)?)
+    }
+
+    /// Fingerprint of the line at the 1-based `site`, which survives the line
+    /// drift and the whitespace changes caused by edits elsewhere in the source
+    pub fn fingerprint(&self, site: usize) -> Option<String> {
+        let line = self.line(site)?;
+        let normalized = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        Some(hash_source(&normalized)[..16].to_string())
+    }
+}
+
+/// Search `root` recursively for the pass source named `pass` (e.g., `GVN.cpp`)
+fn find_pass_source(root: &Path, pass: &str) -> Option<PathBuf> {
+    if root.is_file() {
+        return (root.file_name()? == pass).then(|| root.to_path_buf());
+    }
+
+    let candidate = root.join(pass);
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+
+    let mut sub_dirs: Vec<PathBuf> = root
+        .read_dir()
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && !path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    sub_dirs.sort();
+    sub_dirs
+        .iter()
+        .find_map(|sub_dir| find_pass_source(sub_dir, pass))
+}
+
+/// Pass sources under a source root, loaded on demand by the `Pass:` field
+pub struct SourceRoot {
+    root: PathBuf,
+    sources: HashMap<String, Option<PassSource>>,
+}
+
+impl SourceRoot {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, pass: &str) -> Option<&PassSource> {
+        let root = &self.root;
+        self.sources
+            .entry(pass.to_string())
+            .or_insert_with(|| {
+                find_pass_source(root, pass).and_then(|path| PassSource::load(&path))
+            })
+            .as_ref()
+    }
+
+    /// The root as a `file://` URI of a directory
+    pub fn root_uri(&self) -> String {
+        let root = self.root.canonicalize().unwrap_or(self.root.clone());
+        let root = if root.is_file() {
+            root.parent().map(Path::to_path_buf).unwrap_or(root)
+        } else {
+            root
+        };
+        format!(
+            "file://{}/",
+            root.display().to_string().trim_end_matches('/')
+        )
+    }
+
+    /// Path of a pass source relative to the root, as used in the patch headers
+    pub fn relative_path(&self, source: &PassSource) -> String {
+        match source.path.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => {
+                source.path.file_name().unwrap().to_string_lossy().into()
+            }
+            Ok(relative) => relative.to_string_lossy().into(),
+            Err(_) => source.path.to_string_lossy().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_one_based() {
+        let source = PassSource {
+            path: "Foo.cpp".into(),
+            code: "void f() {\n  I->dropLocation();\n}\n".to_string(),
+        };
+        assert_eq!(source.line(2), Some("  I->dropLocation();"));
+        assert_eq!(source.line(0), None);
+        assert_eq!(source.line(4), None);
+    }
+
+    #[test]
+    fn relative_paths() {
+        let root = SourceRoot::new(Path::new("/llvm/lib/Transforms"));
+        let source = |path: &str| PassSource {
+            path: path.into(),
+            code: String::new(),
+        };
+        assert_eq!(
+            root.relative_path(&source("/llvm/lib/Transforms/Scalar/GVN.cpp")),
+            "Scalar/GVN.cpp"
+        );
+        assert_eq!(root.relative_path(&source("/tmp/GVN.cpp")), "/tmp/GVN.cpp");
+    }
+}
@@ -0,0 +1,436 @@
+use colored::Colorize;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::{Node, Parser, Tree};
+
+use crate::ast::AstNode;
+use crate::edit::{apply_edits, Edit, EditKind};
+use crate::log::{LogRecord, Verdict};
+use crate::matcher::{ConstructKind, DLUpdateKind, FuncMatch};
+use crate::metadata::{InstrumentMeta, SiteKind};
+use crate::source::{PassSource, SourceRoot};
+use crate::traverse::get_children_of_kind;
+
+/// A concrete patch for one violation
+pub struct Suggestion {
+    /// What the patch does, e.g., "preserve the debug location of `I` on `NewI`"
+    pub summary: String,
+    pub edits: Vec<Edit>,
+}
+
+fn parse_source(code: &str) -> Tree {
+    let mut parser = Parser::new();
+    parser
+        .set_language(&tree_sitter_cpp::language())
+        .expect("Error loading grammar");
+    parser.parse(code, None).expect("Failed to parse the code!")
+}
+
+/// Whether a recorded name can be used as-is in the emitted code, e.g., `NewI` or `&I`
+fn is_plain_name(name: &str) -> bool {
+    let name = name.strip_prefix('&').unwrap_or(name);
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Member access on a recorded name, where `&I` denotes a reference `I`
+fn member_access(name: &str) -> String {
+    match name.strip_prefix('&') {
+        Some(reference) => format!("{}.", reference),
+        None => format!("{}->", name),
+    }
+}
+
+/// Return the innermost statement directly in a block that covers the 1-based line `site`
+fn get_stmt_at<'tree>(root: &Node<'tree>, site: usize) -> Option<Node<'tree>> {
+    let row = site.checked_sub(1)?;
+    let mut stmts: Vec<Node> = get_children_of_kind(root, "compound_statement")
+        .iter()
+        .flat_map(|block| {
+            let mut cursor = block.walk();
+            block.named_children(&mut cursor).collect::<Vec<_>>()
+        })
+        .filter(|stmt| stmt.start_position().row <= row && row <= stmt.end_position().row)
+        .collect();
+    stmts.sort_by_key(|stmt| stmt.end_byte() - stmt.start_byte());
+    stmts.first().copied()
+}
+
+/// Return the statement of the debug location update call at the 1-based line `site`
+fn get_update_stmt_at<'tree>(root: &Node<'tree>, site: usize, code: &str) -> Option<Node<'tree>> {
+    get_children_of_kind(root, "call_expression")
+        .into_iter()
+        .filter(|call| call.row() == site)
+        .find(|call| {
+            call.child_by_field_name("function")
+                .and_then(|callee| callee.child_by_field_name("field"))
+                .is_some_and(|field| field.to_source(code).is_debugloc_update().is_some())
+        })
+        .and_then(|call| call.parent())
+        .filter(|parent| parent.kind() == "expression_statement")
+}
+
+/// Indentation of the line where `node` starts
+fn indent_of(node: &Node, code: &str) -> String {
+    let line_start = code[..node.start_byte()]
+        .rfind('\n')
+        .map_or(0, |pos| pos + 1);
+    code[line_start..]
+        .chars()
+        .take_while(|c| c.is_whitespace() && *c != '\n')
+        .collect()
+}
+
+/// Build the patch for a failed record from the instrumentation metadata of its pass
+pub fn suggest(
+    record: &LogRecord,
+    source: &PassSource,
+    meta: &InstrumentMeta,
+) -> Result<Suggestion, String> {
+    if record.verdict != Verdict::Fail {
+        return Err("not a violation".to_string());
+    }
+
+    /* The instruction whose debug location is to be updated */
+    let construct = meta
+        .sites_at(record.construct_site, SiteKind::Construct)
+        .find(|info| is_plain_name(&info.dst));
+    let dst = match construct {
+        Some(info) => info.dst.clone(),
+        None => record
+            .replace_sites
+            .iter()
+            .flat_map(|&site| meta.sites_at(site, SiteKind::Replace))
+            .map(|info| info.dst.clone())
+            .find(|dst| is_plain_name(dst))
+            .ok_or("the constructed instruction is unnamed")?,
+    };
+
+    /* The replaced instructions, or the original one of a clone */
+    let mut olds: Vec<String> = vec![];
+    for &site in &record.replace_sites {
+        let replaces: Vec<_> = meta.sites_at(site, SiteKind::Replace).collect();
+        let replace = replaces
+            .iter()
+            .find(|info| info.dst == dst)
+            .or(replaces.first());
+        if let Some(replace) = replace {
+            olds.extend(replace.srcs.iter().cloned());
+        }
+    }
+    if record.construct_kind == ConstructKind::Cloning {
+        if let Some(info) = construct {
+            olds.extend(info.srcs.iter().cloned());
+        }
+    }
+    olds.retain(|old| is_plain_name(old) && *old != dst);
+    olds.dedup();
+
+    let (update_stmt, summary) = match record.expected {
+        DLUpdateKind::Preserving => {
+            let old = olds
+                .first()
+                .ok_or("no replaced instruction to preserve the debug location from")?;
+            (
+                format!(
+                    "{}setDebugLoc({}getDebugLoc());",
+                    member_access(&dst),
+                    member_access(old)
+                ),
+                format!("preserve the debug location of `{}` on `{}`", old, dst),
+            )
+        }
+        DLUpdateKind::Merging => {
+            if olds.len() < 2 {
+                return Err("less than two replaced instructions to merge".to_string());
+            }
+            (
+                format!(
+                    "{}applyMergedLocation({}getDebugLoc(), {}getDebugLoc());",
+                    member_access(&dst),
+                    member_access(&olds[0]),
+                    member_access(&olds[1])
+                ),
+                format!(
+                    "merge the debug locations of `{}` and `{}` on `{}`",
+                    olds[0], olds[1], dst
+                ),
+            )
+        }
+        DLUpdateKind::Dropping => (
+            format!("{}dropLocation();", member_access(&dst)),
+            format!("drop the debug location of `{}`", dst),
+        ),
+        DLUpdateKind::Others => return Err("any update is acceptable".to_string()),
+    };
+
+    let code = &source.code;
+    let tree = parse_source(code);
+    let root = tree.root_node();
+    let edit = match &record.update {
+        /* Replace the wrong update */
+        Some((update_site, _)) => {
+            let stmt = get_update_stmt_at(&root, *update_site, code)
+                .ok_or(format!("cannot find the update at line {}", update_site))?;
+            Edit::new_replace(update_stmt, stmt.start_byte(), stmt.end_byte())
+        }
+        /* Add the missing update after the last construct or replace site */
+        None => {
+            let last_site = record
+                .replace_sites
+                .iter()
+                .copied()
+                .chain([record.construct_site])
+                .max()
+                .unwrap();
+            let stmt = get_stmt_at(&root, last_site)
+                .ok_or(format!("cannot find the statement at line {}", last_site))?;
+            let insert_str = format!("\n{}{}", indent_of(&stmt, code), update_stmt);
+            Edit::new_insert(insert_str, stmt.end_byte())
+        }
+    };
+
+    Ok(Suggestion {
+        summary,
+        edits: vec![edit],
+    })
+}
+
+/// Render the suggestion as a unified diff against the pass source
+pub fn to_unified_diff(suggestion: &Suggestion, source: &PassSource, label: &str) -> String {
+    let patched = apply_edits(&source.code, &suggestion.edits);
+    TextDiff::from_lines(&source.code, &patched)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{}", label), &format!("b/{}", label))
+        .to_string()
+}
+
+/// The byte ranges of the syntax errors of the code, which the pass sources
+/// may have before any patch, e.g., around the macros
+fn error_ranges(code: &str) -> Vec<Range<usize>> {
+    let tree = parse_source(code);
+    let mut ranges = vec![];
+    let mut nodes = vec![tree.root_node()];
+    while let Some(node) = nodes.pop() {
+        if node.is_error() || node.is_missing() {
+            ranges.push(node.byte_range());
+        } else if node.has_error() {
+            let mut cursor = node.walk();
+            nodes.extend(node.children(&mut cursor));
+        }
+    }
+    ranges
+}
+
+/// The byte ranges of the edited code in the patched code, see [`apply_edits`]
+fn edited_ranges(edits: &[Edit]) -> Vec<Range<usize>> {
+    let mut ordered: Vec<&Edit> = edits.iter().collect();
+    ordered.sort_by_key(|edit| (edit.start_pos, edit.kind != EditKind::Insert));
+
+    let mut shift = 0isize;
+    let mut ranges = vec![];
+    for edit in ordered {
+        let start = edit.start_pos.saturating_add_signed(shift);
+        ranges.push(start..start + edit.content.len());
+        let removed = match edit.kind {
+            EditKind::Insert => 0,
+            EditKind::Replace(end_pos) => end_pos - edit.start_pos,
+        };
+        shift += edit.content.len() as isize - removed as isize;
+    }
+    ranges
+}
+
+/// Apply the suggestions to the pass source, refusing the result if the patches
+/// break its parse, i.e., add syntax errors or touch an erroneous part
+pub fn apply_suggestions(
+    source: &PassSource,
+    suggestions: &[&Suggestion],
+) -> Result<String, String> {
+    let edits: Vec<Edit> = suggestions
+        .iter()
+        .flat_map(|suggestion| suggestion.edits.iter().cloned())
+        .collect();
+    let patched = apply_edits(&source.code, &edits);
+
+    let errors = error_ranges(&patched);
+    let touches_error = edited_ranges(&edits).iter().any(|edited| {
+        errors
+            .iter()
+            .any(|error| error.start < edited.end && edited.start < error.end)
+    });
+    if errors.len() > error_ranges(&source.code).len() || touches_error {
+        return Err(format!(
+            "{} does not parse after the patches",
+            source.path.display()
+        ));
+    }
+    Ok(patched)
+}
+
+/// Render the suggestion as a mail in the format of `git format-patch`, which
+/// can be applied by `git am` at the source root
+pub fn to_format_patch(
+    suggestion: &Suggestion,
+    record: &LogRecord,
+    source: &PassSource,
+    label: &str,
+    author: &str,
+    (idx, total): (usize, usize),
+) -> String {
+    let file_name = source.path.file_name().unwrap().to_string_lossy();
+    let diff = to_unified_diff(suggestion, source, label);
+    let insertions = diff
+        .lines()
+        .filter(|line| line.starts_with('+') && !line.starts_with("+++"))
+        .count();
+    let deletions = diff
+        .lines()
+        .filter(|line| line.starts_with('-') && !line.starts_with("---"))
+        .count();
+
+    let mut summary = suggestion.summary.clone();
+    summary[..1].make_ascii_uppercase();
+    format!(
+        "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001\n\
+         From: {author}\n\
+         Subject: [PATCH {idx}/{total}] [DISan] {summary} in {file_name}\n\
+         \n\
+         DISan reported the following debug location update violation:\n\
+         \n\
+         \x20   {record}\n\
+         ---\n\
+         \x20{label} | {changes} {bar}\n\
+         \x201 file changed, {insertions} insertion(+), {deletions} deletion(-)\n\
+         \n\
+         diff --git a/{label} b/{label}\n\
+         {diff}\
+         -- \n\
+         disan\n\n",
+        changes = insertions + deletions,
+        bar = "+".repeat(insertions) + &"-".repeat(deletions),
+    )
+}
+
+/// The suggestions for the violations in one pass
+pub struct PassSuggestions<'r> {
+    pub source: PassSource,
+    /// Path of the source relative to the source root
+    pub label: String,
+    pub suggestions: Vec<(&'r LogRecord, Suggestion)>,
+}
+
+fn warn_skip(what: &str, reason: &str) {
+    eprintln!(
+        "{}{} {}: {}",
+        "warning".yellow().bold(),
+        ": Cannot suggest a patch for".bold(),
+        what,
+        reason,
+    );
+}
+
+/// Suggest patches for the violations in the records, grouped by pass. The
+/// violations that cannot be patched are skipped with warnings, as are the
+/// passes whose sources have changed since the instrumentation.
+pub fn collect_suggestions<'r>(
+    records: &'r [LogRecord],
+    source_root: &mut SourceRoot,
+    meta_dir: &Path,
+) -> Vec<PassSuggestions<'r>> {
+    let mut by_pass: BTreeMap<&str, Vec<&LogRecord>> = BTreeMap::new();
+    for record in records.iter().filter(|r| r.verdict == Verdict::Fail) {
+        by_pass.entry(&record.pass).or_default().push(record);
+    }
+
+    let mut pass_suggestions = vec![];
+    for (pass, records) in by_pass {
+        let Some(source) = source_root.get(pass).cloned() else {
+            warn_skip(pass, "cannot find the pass source under the source root");
+            continue;
+        };
+        let meta = InstrumentMeta::load(&InstrumentMeta::path_of(meta_dir, pass))
+            .and_then(|meta| meta.check_source(&source.code).map(|_| meta));
+        let meta = match meta {
+            Ok(meta) => meta,
+            Err(e) => {
+                warn_skip(pass, &e);
+                continue;
+            }
+        };
+
+        let mut suggestions = vec![];
+        for record in records {
+            match suggest(record, &source, &meta) {
+                Ok(suggestion) => suggestions.push((record, suggestion)),
+                Err(e) => warn_skip(&record.to_string(), &e),
+            }
+        }
+        pass_suggestions.push(PassSuggestions {
+            label: source_root.relative_path(&source),
+            source,
+            suggestions,
+        });
+    }
+    pass_suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn member_access_by_prefix() {
+        assert_eq!(member_access("NewI"), "NewI->");
+        assert_eq!(member_access("&I"), "I.");
+    }
+
+    /// A source failing to parse around a macro before any patch
+    const SOURCE: &str = "\
+DEBUG_COUNTER(Counter, \"c\", \"d\") LLVM_ATTRIBUTE_UNUSED static int X;
+void f(Instruction *I, Instruction *NewI) {
+  I->replaceAllUsesWith(NewI);
+}
+";
+
+    fn apply_insert(content: &str, pos: usize) -> Result<String, String> {
+        let source = PassSource {
+            path: "Foo.cpp".into(),
+            code: SOURCE.to_string(),
+        };
+        let suggestion = Suggestion {
+            summary: String::new(),
+            edits: vec![Edit::new_insert(content.to_string(), pos)],
+        };
+        apply_suggestions(&source, &[&suggestion])
+    }
+
+    #[test]
+    fn apply_despite_earlier_errors() {
+        let pos = SOURCE.find("  I->").unwrap();
+        let patched = apply_insert("  NewI->setDebugLoc(I->getDebugLoc());\n", pos).unwrap();
+        assert!(patched.contains("NewI->setDebugLoc(I->getDebugLoc());\n  I->"));
+    }
+
+    #[test]
+    fn refuse_new_errors() {
+        let pos = SOURCE.find("  I->").unwrap();
+        assert!(apply_insert("  NewI->setDebugLoc(;\n", pos).is_err());
+    }
+
+    #[test]
+    fn refuse_patching_errors() {
+        assert!(apply_insert(" NewI->dropLocation();", SOURCE.find("LLVM_").unwrap()).is_err());
+    }
+
+    #[test]
+    fn plain_names() {
+        assert!(is_plain_name("&I"));
+        assert!(is_plain_name("NewI"));
+        assert!(!is_plain_name("&*It"));
+        assert!(!is_plain_name("cast<Instruction>(V)"));
+    }
+}
@@ -2,6 +2,16 @@ use tree_sitter::Node;
 
 use crate::ast::AstNode;
 
+/// The casts whose template argument is the type of the result, e.g., `dyn_cast<PHINode>(V)`
+pub const CASTS: [&str; 6] = [
+    "cast",
+    "dyn_cast",
+    "cast_or_null",
+    "dyn_cast_or_null",
+    "cast_if_present",
+    "dyn_cast_if_present",
+];
+
 /// Top level collection
 pub fn get_children_of_kind<'tree>(node: &Node<'tree>, kind: &str) -> Vec<Node<'tree>> {
     let mut res = vec![];
@@ -15,33 +25,172 @@ pub fn get_children_of_kind<'tree>(node: &Node<'tree>, kind: &str) -> Vec<Node<'
     res
 }
 
-/// Return the closest parent of `node` matching the `kind`
-pub fn get_parent_of_kind<'tree>(node: &Node<'tree>, kind: &str) -> Option<Node<'tree>> {
+/// Collect the nodes of `kind` under `node` that belong to its own scope, i.e.,
+/// without descending into the matches or into the nested scopes of `scopes`,
+/// e.g., the returns of a function outside its lambdas
+pub fn get_children_of_kind_in_scope<'tree>(
+    node: &Node<'tree>,
+    kind: &str,
+    scopes: &[&str],
+) -> Vec<Node<'tree>> {
+    let mut res = vec![];
+    for cid in 0..node.child_count() {
+        let child = node.child(cid).unwrap();
+        if child.kind() == kind {
+            res.push(child);
+        } else if !scopes.contains(&child.kind()) {
+            res.append(&mut get_children_of_kind_in_scope(&child, kind, scopes));
+        }
+    }
+    res
+}
+
+/// Node kinds bounding an ancestor search, i.e., a call never belongs to a
+/// declaration or an assignment beyond its own statement, lambda or block
+const SEARCH_BOUNDARIES: [&str; 12] = [
+    "compound_statement",
+    "lambda_expression",
+    "expression_statement",
+    "return_statement",
+    "if_statement",
+    "for_statement",
+    "for_range_loop",
+    "while_statement",
+    "do_statement",
+    "switch_statement",
+    "condition_clause",
+    "function_definition",
+];
+
+/// Return the closest parent of `node` matching the `kind`, without leaving
+/// the statement, lambda or block enclosing `node`
+pub fn get_bounded_parent_of_kind<'tree>(node: &Node<'tree>, kind: &str) -> Option<Node<'tree>> {
     let mut nullable_parent = node.parent();
-    let mut the_parent = None;
-    while nullable_parent.is_some() {
-        let parent = nullable_parent.unwrap();
+    while let Some(parent) = nullable_parent {
         if parent.kind() == kind {
-            the_parent = Some(parent);
+            return Some(parent);
+        }
+        if SEARCH_BOUNDARIES.contains(&parent.kind()) {
             break;
         }
         nullable_parent = parent.parent();
     }
+    None
+}
 
-    the_parent
+/// Return the statement (or the condition, lambda, ...) holding `node`, i.e.,
+/// the extent of the code the hooks of a site may wrap
+pub fn get_stmt_of<'tree>(node: &Node<'tree>) -> Node<'tree> {
+    let mut stmt = *node;
+    while let Some(parent) = stmt.parent() {
+        if parent.kind() == "compound_statement" || parent.kind().starts_with("preproc_") {
+            break;
+        }
+        if SEARCH_BOUNDARIES.contains(&parent.kind()) {
+            return parent;
+        }
+        stmt = parent;
+    }
+    stmt
 }
 
-pub fn get_var_name_from_decl<'tree>(decl: &Node<'tree>) -> Node<'tree> {
-    let var_name = decl
-        .child_by_field_name("declarator")
-        .unwrap()
-        .child_by_field_name("declarator")
-        .unwrap();
-    if var_name.kind() == "pointer_declarator" {
-        var_name.child_by_field_name("declarator").unwrap()
-    } else {
-        var_name
+/// Return the outermost parenthesized expression wrapping `expr`, e.g., `((expr))`
+fn get_outermost_parens<'tree>(expr: &Node<'tree>) -> Node<'tree> {
+    let mut outermost = *expr;
+    while let Some(parent) = outermost.parent() {
+        if parent.kind() != "parenthesized_expression" {
+            break;
+        }
+        outermost = parent;
+    }
+    outermost
+}
+
+/// Return the outermost cast taking `expr` as its only argument, e.g.,
+/// `cast<Instruction>(I->clone())` of `I->clone()`, or `expr` itself
+pub fn get_outermost_cast<'tree>(expr: &Node<'tree>, code: &str) -> Node<'tree> {
+    let mut outermost = *expr;
+    while let Some(call) = get_outermost_parens(&outermost)
+        .parent()
+        .filter(|parent| parent.kind() == "argument_list" && get_arguments(parent).len() == 1)
+        .and_then(|arguments| arguments.parent())
+    {
+        let mut function = call.child_by_field_name("function").unwrap();
+        // llvm::cast<Instruction>(..)
+        if function.kind() == "qualified_identifier" {
+            function = function.child_by_field_name("name").unwrap();
+        }
+        let is_cast = function.kind() == "template_function"
+            && CASTS.contains(
+                &function
+                    .child_by_field_name("name")
+                    .unwrap()
+                    .to_source(code)
+                    .as_str(),
+            );
+        if !is_cast {
+            break;
+        }
+        outermost = call;
+    }
+    outermost
+}
+
+/// Return the declaration statement and the declared variable if `expr` is
+/// exactly the initializer value of the declaration, e.g., `auto *X = (expr);`
+pub fn get_decl_initialized_by<'tree>(expr: &Node<'tree>) -> Option<(Node<'tree>, Node<'tree>)> {
+    let decl = get_bounded_parent_of_kind(expr, "declaration")?;
+    if !matches!(
+        decl.parent().map(|parent| parent.kind()),
+        Some("compound_statement" | "case_statement")
+    ) {
+        // Declarations in `for (..; ..; ..)` or `if (..; ..)` leave no room for hooks
+        return None;
+    }
+
+    let value = get_outermost_parens(expr);
+    let init_decl = value.parent()?;
+    if init_decl.kind() != "init_declarator"
+        || init_decl.parent() != Some(decl)
+        || init_decl.child_by_field_name("value") != Some(value)
+    {
+        return None;
+    }
+
+    Some((decl, get_var_name_from_init_decl(&init_decl)))
+}
+
+/// Return the assignment statement and the assigned variable if `expr` is
+/// exactly the right-hand side of the assignment, e.g., `X = (expr);`
+pub fn get_assign_stmt_of<'tree>(expr: &Node<'tree>) -> Option<(Node<'tree>, Node<'tree>)> {
+    let assign = get_bounded_parent_of_kind(expr, "assignment_expression")?;
+    let value = get_outermost_parens(expr);
+    if assign.child_by_field_name("right") != Some(value) {
+        return None;
     }
+
+    let stmt = assign
+        .parent()
+        .filter(|parent| parent.kind() == "expression_statement")?;
+    Some((stmt, get_var_name_from_assign(&assign)))
+}
+
+pub fn get_var_name_from_init_decl<'tree>(init_decl: &Node<'tree>) -> Node<'tree> {
+    assert_eq!(init_decl.kind(), "init_declarator");
+    let mut var_name = init_decl.child_by_field_name("declarator").unwrap();
+    while matches!(
+        var_name.kind(),
+        "pointer_declarator" | "reference_declarator"
+    ) {
+        var_name = match var_name.child_by_field_name("declarator") {
+            Some(declarator) => declarator,
+            // The reference declarator has no field names
+            None => var_name
+                .named_child(var_name.named_child_count() - 1)
+                .unwrap(),
+        };
+    }
+    var_name
 }
 
 pub fn get_var_name_from_assign<'tree>(assign: &Node<'tree>) -> Node<'tree> {
@@ -54,16 +203,93 @@ pub fn get_var_name_from_assign<'tree>(assign: &Node<'tree>) -> Node<'tree> {
     }
 }
 
-pub fn get_fn_identifier<'tree>(fn_def: &Node<'tree>) -> Node<'tree> {
-    // Only one function declarator in one function definition
-    let declarator = get_children_of_kind(fn_def, "function_declarator")[0];
-    let identifier = declarator.child_by_field_name("declarator").unwrap();
-    let identifier = if identifier.kind() == "function_declarator" {
-        identifier.child_by_field_name("declarator").unwrap()
-    } else {
-        identifier
-    };
-    identifier
+/// Return the declarator nested in `declarator`, e.g., `f()` in `*f()` of a
+/// function returning a pointer, or None for a name
+fn get_nested_declarator<'tree>(declarator: &Node<'tree>) -> Option<Node<'tree>> {
+    match declarator.kind() {
+        "function_declarator" | "pointer_declarator" | "attributed_declarator" => {
+            declarator.child_by_field_name("declarator")
+        }
+        "reference_declarator" | "parenthesized_declarator" => declarator.named_child(0),
+        _ => None,
+    }
+}
+
+/// Return the declarator of the function itself, i.e., the innermost function
+/// declarator, since the outer ones of e.g. `void (*f(int))(char)` belong to
+/// the returned function pointer
+pub fn get_fn_declarator<'tree>(fn_def: &Node<'tree>) -> Option<Node<'tree>> {
+    let mut declarator = fn_def.child_by_field_name("declarator");
+    let mut fn_declarator = None;
+    while let Some(node) = declarator {
+        if node.kind() == "function_declarator" {
+            fn_declarator = Some(node);
+        }
+        declarator = get_nested_declarator(&node);
+    }
+    fn_declarator
+}
+
+/// Return the name of the function as declared, e.g., `run`, `FooPass<T>::run`
+/// or `operator()`
+pub fn get_fn_identifier<'tree>(fn_def: &Node<'tree>) -> Option<Node<'tree>> {
+    get_fn_declarator(fn_def)?.child_by_field_name("declarator")
+}
+
+/// Return the name without the template arguments, e.g., `FooPass::run` of `FooPass<T>::run`
+fn get_plain_name(name: &Node, code: &str) -> String {
+    match name.kind() {
+        "qualified_identifier" => {
+            let scope = name
+                .child_by_field_name("scope")
+                .map_or(String::new(), |scope| get_plain_name(&scope, code));
+            let name = name
+                .child_by_field_name("name")
+                .map_or(String::new(), |name| get_plain_name(&name, code));
+            format!("{}::{}", scope, name)
+        }
+        "template_type" | "template_function" | "template_method" => name
+            .child_by_field_name("name")
+            .map_or(String::new(), |name| get_plain_name(&name, code)),
+        _ => code[name.byte_range()]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// Return the name of the function qualified by the classes it is defined in
+/// and without the template arguments, e.g., `FooPass::run` for both
+/// `template <..> PreservedAnalyses FooPass<T>::run(..)` and the `run` defined
+/// in `struct FooPass { .. }`
+pub fn get_fn_qualified_name(fn_def: &Node, code: &str) -> Option<String> {
+    let mut name = get_plain_name(&get_fn_identifier(fn_def)?, code);
+    let mut ancestor = fn_def.parent();
+    while let Some(node) = ancestor {
+        if node.kind() == "field_declaration_list" {
+            if let Some(class_name) = node.parent().and_then(|c| c.child_by_field_name("name")) {
+                name = format!("{}::{}", get_plain_name(&class_name, code), name);
+            }
+        }
+        ancestor = node.parent();
+    }
+    Some(name)
+}
+
+/// Named arguments of an `argument_list`, skipping punctuation and comments
+/// (e.g. the `/*InsertBefore=*/` annotations LLVM puts before arguments)
+pub fn get_arguments<'tree>(arguments: &Node<'tree>) -> Vec<Node<'tree>> {
+    assert_eq!(arguments.kind(), "argument_list");
+    let mut cursor = arguments.walk();
+    arguments
+        .named_children(&mut cursor)
+        .filter(|arg| arg.kind() != "comment")
+        .collect()
+}
+
+/// Return the `pos`-th (0-based) argument of an `argument_list`
+pub fn get_argument<'tree>(arguments: &Node<'tree>, pos: usize) -> Option<Node<'tree>> {
+    get_arguments(arguments).get(pos).copied()
 }
 
 pub fn get_ident_from_call<'tree>(
@@ -83,3 +309,67 @@ pub fn get_ident_from_call<'tree>(
     }
     None
 }
+
+/// Return the `getInstList` access and the inserted instruction of an insertion
+/// into the instruction list of a block, i.e., `BB->getInstList().insert(It, I)`
+pub fn get_inst_list_insertion<'tree>(
+    callee: &Node<'tree>,
+    args: &[Node<'tree>],
+    code: &str,
+) -> Option<(Node<'tree>, Node<'tree>)> {
+    let inst_list = callee.child_by_field_name("argument")?;
+    let getter = inst_list.child_by_field_name("function")?;
+    if inst_list.kind() != "call_expression"
+        || getter.kind() != "field_expression"
+        || getter.child_by_field_name("field")?.to_source(code) != "getInstList"
+        || args.len() != 2
+    {
+        return None;
+    }
+    Some((getter, args[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::{Parser, Tree};
+
+    fn parse(code: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_cpp::language()).unwrap();
+        parser.parse(code, None).unwrap()
+    }
+
+    fn arguments_of(code: &str) -> Vec<String> {
+        let tree = parse(code);
+        let call = get_children_of_kind(&tree.root_node(), "call_expression")[0];
+        let arguments = call.child_by_field_name("arguments").unwrap();
+        get_arguments(&arguments)
+            .iter()
+            .map(|arg| arg.to_source(code))
+            .collect()
+    }
+
+    #[test]
+    fn arguments_skip_comments() {
+        let code = "void f() { I->replaceUsesOfWith(/*From=*/Old, /*To=*/New); }";
+        assert_eq!(arguments_of(code), ["Old", "New"]);
+    }
+
+    #[test]
+    fn argument_by_position() {
+        let code =
+            "void f() { BinaryOperator::Create(Op, /*S1=*/A, B, \"\", /*InsertBefore=*/I); }";
+        let tree = parse(code);
+        let call = get_children_of_kind(&tree.root_node(), "call_expression")[0];
+        let arguments = call.child_by_field_name("arguments").unwrap();
+        assert_eq!(get_argument(&arguments, 1).unwrap().to_source(code), "A");
+        assert_eq!(get_argument(&arguments, 4).unwrap().to_source(code), "I");
+        assert!(get_argument(&arguments, 5).is_none());
+    }
+
+    #[test]
+    fn no_arguments() {
+        assert!(arguments_of("void f() { I->clone(); }").is_empty());
+    }
+}
@@ -0,0 +1,576 @@
+use tree_sitter::Node;
+
+use crate::ast::AstNode;
+use crate::traverse::{get_argument, get_children_of_kind, get_fn_declarator, CASTS};
+
+/// The iterators over the instructions of a block, e.g., `BasicBlock::iterator`
+const ITERATOR_SCOPES: [&str; 2] = ["BasicBlock", "InstListType"];
+const ITERATORS: [&str; 4] = [
+    "iterator",
+    "const_iterator",
+    "reverse_iterator",
+    "const_reverse_iterator",
+];
+
+/// The range adaptors yielding the elements of their first argument
+const RANGE_ADAPTORS: [&str; 3] = ["make_early_inc_range", "reverse", "drop_begin"];
+
+/// The kinds of the IR objects the hooks tell apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrClass {
+    /// `Instruction` or one of its subclasses, e.g., `PHINode`
+    Instruction,
+    BasicBlock,
+    /// `Value` or `User`, which may or may not be an instruction
+    Value,
+    /// An iterator over the instructions of a block, e.g., `BasicBlock::iterator`
+    Iterator,
+    /// Anything else, e.g., `VPInstruction` or `MachineInstr`
+    Other,
+}
+
+/// The type of an expression, as far as the declarations in scope tell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExprType {
+    pub class: IrClass,
+    /// A pointer rather than an object or a reference
+    pub is_pointer: bool,
+}
+
+impl ExprType {
+    fn pointer(class: IrClass) -> Self {
+        Self {
+            class,
+            is_pointer: true,
+        }
+    }
+
+    fn object(class: IrClass) -> Self {
+        Self {
+            class,
+            is_pointer: false,
+        }
+    }
+
+    /// Whether it is an instruction, through a pointer, a reference or an iterator
+    pub fn is_instruction(&self) -> bool {
+        matches!(self.class, IrClass::Instruction | IrClass::Iterator)
+    }
+}
+
+/// Classify a type by its unqualified name, e.g., `Instruction` of `llvm::Instruction`
+fn classify(name: &str) -> IrClass {
+    match name {
+        "Instruction" | "BinaryOperator" | "UnaryOperator" | "PHINode" | "CallBase" => {
+            IrClass::Instruction
+        }
+        "BasicBlock" => IrClass::BasicBlock,
+        "Value" | "User" => IrClass::Value,
+        // E.g., `CallInst`, `DbgVariableIntrinsic`, but not `VPInstruction`
+        _ if name.ends_with("Inst") || name.ends_with("Intrinsic") => IrClass::Instruction,
+        _ => IrClass::Other,
+    }
+}
+
+/// The unqualified name of a type, without the template arguments
+fn type_name(ty: &Node, code: &str) -> String {
+    match ty.kind() {
+        "qualified_identifier" | "template_type" => ty
+            .child_by_field_name("name")
+            .map_or(String::new(), |name| type_name(&name, code)),
+        "type_descriptor" => ty
+            .child_by_field_name("type")
+            .map_or(String::new(), |ty| type_name(&ty, code)),
+        _ => ty.to_source(code),
+    }
+}
+
+/// The names of a qualified type from the outermost scope, e.g., `[BasicBlock, iterator]`
+fn qualified_names(ty: &Node, code: &str) -> Vec<String> {
+    let mut names = vec![];
+    let mut ty = *ty;
+    while ty.kind() == "qualified_identifier" {
+        if let Some(scope) = ty.child_by_field_name("scope") {
+            names.push(type_name(&scope, code));
+        }
+        let Some(name) = ty.child_by_field_name("name") else {
+            return names;
+        };
+        ty = name;
+    }
+    names.push(type_name(&ty, code));
+    names
+}
+
+/// The type aliased by `name` and its pointers, by a `typedef` or a `using` in scope
+fn find_alias<'tree>(ty: &Node<'tree>, name: &str, code: &str) -> Option<(Node<'tree>, usize)> {
+    let mut ancestor = ty.parent();
+    while let Some(scope) = ancestor {
+        let mut cursor = scope.walk();
+        let mut aliases: Vec<Node> = scope
+            .named_children(&mut cursor)
+            .filter(|child| {
+                matches!(child.kind(), "type_definition" | "alias_declaration")
+                    && child.end_byte() <= ty.start_byte()
+            })
+            .collect();
+        aliases.reverse();
+        for alias in aliases {
+            match alias.kind() {
+                // typedef Instruction *IP, I;
+                "type_definition" => {
+                    let mut cursor = alias.walk();
+                    let declarators: Vec<Node> = alias
+                        .children_by_field_name("declarator", &mut cursor)
+                        .collect();
+                    for declarator in declarators {
+                        let mut declarator = declarator;
+                        let mut pointers = 0;
+                        while declarator.kind() == "pointer_declarator" {
+                            pointers += 1;
+                            declarator = declarator.child_by_field_name("declarator")?;
+                        }
+                        if declarator.to_source(code) == name {
+                            return Some((alias.child_by_field_name("type")?, pointers));
+                        }
+                    }
+                }
+                // using IP = Instruction *;
+                _ => {
+                    if alias.child_by_field_name("name")?.to_source(code) == name {
+                        return Some((alias.child_by_field_name("type")?, 0));
+                    }
+                }
+            }
+        }
+        ancestor = scope.parent();
+    }
+    None
+}
+
+/// The class of a type and the number of pointers its aliases add
+fn resolve_type(ty: &Node, code: &str) -> (IrClass, usize) {
+    match ty.kind() {
+        "type_descriptor" => {
+            let Some(inner) = ty.child_by_field_name("type") else {
+                return (IrClass::Other, 0);
+            };
+            let (class, pointers) = resolve_type(&inner, code);
+            let mut declarator = ty.child_by_field_name("declarator");
+            let mut declared = 0;
+            while let Some(pointer) =
+                declarator.filter(|declarator| declarator.kind() == "abstract_pointer_declarator")
+            {
+                declared += 1;
+                declarator = pointer.child_by_field_name("declarator");
+            }
+            (class, pointers + declared)
+        }
+        "qualified_identifier" => {
+            let names = qualified_names(ty, code);
+            let is_iterator = match names.as_slice() {
+                [.., scope, name] => {
+                    ITERATOR_SCOPES.contains(&scope.as_str()) && ITERATORS.contains(&name.as_str())
+                }
+                _ => false,
+            };
+            if is_iterator {
+                (IrClass::Iterator, 0)
+            } else {
+                (classify(&type_name(ty, code)), 0)
+            }
+        }
+        "type_identifier" => {
+            let name = ty.to_source(code);
+            match find_alias(ty, &name, code) {
+                Some((aliased, pointers)) => {
+                    let (class, aliased_pointers) = resolve_type(&aliased, code);
+                    (class, aliased_pointers + pointers)
+                }
+                None => (classify(&name), 0),
+            }
+        }
+        _ => (classify(&type_name(ty, code)), 0),
+    }
+}
+
+/// The declared name and the number of pointers of a declarator, e.g., `(I, 1)` of `*I`
+fn unwrap_declarator<'tree>(declarator: &Node<'tree>) -> Option<(Node<'tree>, usize)> {
+    let mut declarator = *declarator;
+    let mut pointers = 0;
+    loop {
+        declarator = match declarator.kind() {
+            "identifier" | "structured_binding_declarator" => return Some((declarator, pointers)),
+            "pointer_declarator" => {
+                pointers += 1;
+                declarator.child_by_field_name("declarator")?
+            }
+            "init_declarator" => declarator.child_by_field_name("declarator")?,
+            // The reference declarator has no field names
+            "reference_declarator" | "parenthesized_declarator" => {
+                declarator.named_child(declarator.named_child_count().checked_sub(1)?)?
+            }
+            _ => return None,
+        };
+    }
+}
+
+/// The type of a declared variable, where `auto` takes the type of the initializer
+fn type_of_declared(
+    ty: &Node,
+    pointers: usize,
+    value: Option<Node>,
+    code: &str,
+) -> Option<ExprType> {
+    if ty.kind() == "placeholder_type_specifier" {
+        return type_of(&value?, code);
+    }
+    let (class, aliased_pointers) = resolve_type(ty, code);
+    match pointers + aliased_pointers {
+        0 => Some(ExprType::object(class)),
+        1 => Some(ExprType::pointer(class)),
+        _ => Some(ExprType::object(IrClass::Other)),
+    }
+}
+
+/// Whether the unwrapped declarator declares `name`, see [`unwrap_declarator`]
+fn declares(declared: &Node, name: &str, code: &str) -> bool {
+    match declared.kind() {
+        "structured_binding_declarator" => {
+            let mut cursor = declared.walk();
+            let declares = declared
+                .named_children(&mut cursor)
+                .any(|binding| binding.to_source(code) == name);
+            declares
+        }
+        _ => declared.to_source(code) == name,
+    }
+}
+
+/// The type of the variable `declarator` declares in `decl`
+fn type_declared_by(decl: &Node, declarator: &Node, code: &str) -> Option<ExprType> {
+    let ty = decl.child_by_field_name("type")?;
+    let (declared, pointers) = unwrap_declarator(declarator)?;
+    if declared.kind() == "structured_binding_declarator" {
+        return None;
+    }
+    if decl.kind() == "for_range_loop" && ty.kind() == "placeholder_type_specifier" {
+        // `for (auto &I : BB)` and `for (Instruction &I : make_early_inc_range(*BB))`
+        let mut range = decl.child_by_field_name("right")?;
+        while let Some(inner) = adapted_range(&range, code) {
+            range = inner;
+        }
+        let range_type = type_of(&range, code)?;
+        let is_block = range_type.class == IrClass::BasicBlock && !range_type.is_pointer;
+        return is_block.then_some(ExprType {
+            class: IrClass::Instruction,
+            is_pointer: pointers > 0,
+        });
+    }
+    // The initializer is a field of the init declarator, or of the declaration in a condition
+    let value = match declarator.kind() {
+        "init_declarator" => declarator.child_by_field_name("value"),
+        _ => decl.child_by_field_name("value"),
+    };
+    type_of_declared(&ty, pointers, value, code)
+}
+
+/// The range adapted by a call of a range adaptor, e.g., `BB` of `reverse(BB)`
+fn adapted_range<'tree>(range: &Node<'tree>, code: &str) -> Option<Node<'tree>> {
+    if range.kind() != "call_expression" {
+        return None;
+    }
+    let function = range.child_by_field_name("function")?;
+    if !RANGE_ADAPTORS.contains(&function.to_source(code).trim_start_matches("llvm::")) {
+        return None;
+    }
+    get_argument(&range.child_by_field_name("arguments")?, 0)
+}
+
+/// The declarations visible from `ident` that `scope` holds directly, nearest first
+fn declarations_in<'tree>(scope: &Node<'tree>, ident: &Node) -> Vec<Node<'tree>> {
+    let parameters = match scope.kind() {
+        "function_definition" => get_fn_declarator(scope),
+        "lambda_expression" => scope.child_by_field_name("declarator"),
+        _ => None,
+    }
+    .and_then(|declarator| declarator.child_by_field_name("parameters"));
+
+    let holder = match scope.kind() {
+        "compound_statement" | "case_statement" | "translation_unit" | "for_statement" => *scope,
+        "if_statement" | "while_statement" | "switch_statement" => {
+            match scope.child_by_field_name("condition") {
+                Some(condition) => condition,
+                None => return vec![],
+            }
+        }
+        "for_range_loop" => return vec![*scope],
+        _ => match parameters {
+            Some(parameters) => parameters,
+            None => return vec![],
+        },
+    };
+
+    let mut cursor = holder.walk();
+    let mut decls: Vec<Node> = holder
+        .named_children(&mut cursor)
+        // The initializer of `if (auto [It, New] = ..; New)`
+        .map(|child| match child.kind() {
+            "init_statement" => child.named_child(0).unwrap_or(child),
+            _ => child,
+        })
+        .filter(|child| {
+            matches!(
+                child.kind(),
+                "declaration" | "parameter_declaration" | "optional_parameter_declaration"
+            ) && child.end_byte() <= ident.start_byte()
+        })
+        .collect();
+    decls.reverse();
+    decls
+}
+
+/// The declaration of a variable and its declarator, looked up through the enclosing scopes
+fn find_declaration<'tree>(ident: &Node<'tree>, code: &str) -> Option<(Node<'tree>, Node<'tree>)> {
+    let name = ident.to_source(code);
+    let mut ancestor = ident.parent();
+    while let Some(scope) = ancestor {
+        for decl in declarations_in(&scope, ident) {
+            let mut cursor = decl.walk();
+            let declarator =
+                decl.children_by_field_name("declarator", &mut cursor)
+                    .find(|declarator| {
+                        unwrap_declarator(declarator)
+                            .is_some_and(|(declared, _)| declares(&declared, &name, code))
+                    });
+            if let Some(declarator) = declarator {
+                return Some((decl, declarator));
+            }
+        }
+        // The members and the globals are beyond the function
+        if scope.kind() == "function_definition" {
+            break;
+        }
+        ancestor = scope.parent();
+    }
+    None
+}
+
+fn type_of_variable(ident: &Node, code: &str) -> Option<ExprType> {
+    let (decl, declarator) = find_declaration(ident, code)?;
+    type_declared_by(&decl, &declarator, code)
+}
+
+/// Whether the expression names a structured binding
+pub fn names_structured_binding(expr: &Node, code: &str) -> bool {
+    let is_binding = |ident: &Node| {
+        find_declaration(ident, code).is_some_and(|(_, declarator)| {
+            unwrap_declarator(&declarator)
+                .is_some_and(|(declared, _)| declared.kind() == "structured_binding_declarator")
+        })
+    };
+    get_children_of_kind(expr, "identifier")
+        .iter()
+        .chain(Some(expr).filter(|expr| expr.kind() == "identifier"))
+        .any(is_binding)
+}
+
+/// The type of a call, for the casts, the creations and a few accessors
+fn type_of_call(call: &Node, code: &str) -> Option<ExprType> {
+    let mut function = call.child_by_field_name("function")?;
+    // llvm::cast<PHINode>(V)
+    if let Some(name) = function.child_by_field_name("name").filter(|name| {
+        function.kind() == "qualified_identifier" && name.kind() == "template_function"
+    }) {
+        function = name;
+    }
+    match function.kind() {
+        // cast<PHINode>(V)
+        "template_function" => {
+            let cast = function.child_by_field_name("name")?.to_source(code);
+            if !CASTS.contains(&cast.as_str()) {
+                return None;
+            }
+            let ty = function.child_by_field_name("arguments")?.named_child(0)?;
+            Some(ExprType::pointer(resolve_type(&ty, code).0))
+        }
+        // BinaryOperator::Create(..)
+        "qualified_identifier" => {
+            let scope = function.child_by_field_name("scope")?;
+            let name = function.child_by_field_name("name")?.to_source(code);
+            name.starts_with("Create")
+                .then(|| ExprType::pointer(resolve_type(&scope, code).0))
+        }
+        // I->getParent(), BB.begin(), ..
+        "field_expression" => {
+            let field = function.child_by_field_name("field")?.to_source(code);
+            let receiver = || type_of(&function.child_by_field_name("argument")?, code);
+            match field.as_str() {
+                "clone" => receiver()?
+                    .is_instruction()
+                    .then_some(ExprType::pointer(IrClass::Instruction)),
+                "getParent" => receiver()?
+                    .is_instruction()
+                    .then_some(ExprType::pointer(IrClass::BasicBlock)),
+                // Builder.CreateAdd(..), which may fold into a constant
+                _ if field.starts_with("Create") => Some(ExprType::pointer(IrClass::Value)),
+                // The iterators of the other lists, e.g., `F.begin()`, are no instructions
+                "getIterator" => receiver()?
+                    .is_instruction()
+                    .then_some(ExprType::object(IrClass::Iterator)),
+                "begin" | "end" | "getFirstInsertionPt" | "getFirstNonPHIIt" => {
+                    let is_block = receiver()?.class == IrClass::BasicBlock;
+                    is_block.then_some(ExprType::object(IrClass::Iterator))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The type of an expression, or None if the declarations in scope do not tell
+pub fn type_of(expr: &Node, code: &str) -> Option<ExprType> {
+    match expr.kind() {
+        "identifier" => type_of_variable(expr, code),
+        "parenthesized_expression" => type_of(&expr.named_child(0)?, code),
+        "pointer_expression" => {
+            let operand = type_of(&expr.child_by_field_name("argument")?, code)?;
+            match expr.child_by_field_name("operator")?.kind() {
+                "&" if !operand.is_pointer => Some(ExprType::pointer(operand.class)),
+                "*" if operand.class == IrClass::Iterator => {
+                    Some(ExprType::object(IrClass::Instruction))
+                }
+                "*" if operand.is_pointer => Some(ExprType::object(operand.class)),
+                _ => None,
+            }
+        }
+        "new_expression" => {
+            let ty = expr.child_by_field_name("type")?;
+            Some(ExprType::pointer(resolve_type(&ty, code).0))
+        }
+        "call_expression" => type_of_call(expr, code),
+        _ => None,
+    }
+}
+
+/// The operator taking the expression as a pointer, or `untyped` if its type is unknown
+pub fn address_op(expr: &Node, untyped: &'static str, code: &str) -> &'static str {
+    match type_of(expr, code) {
+        Some(ty) if ty.is_pointer => "",
+        Some(ty) if ty.class == IrClass::Iterator => "&*",
+        Some(_) => "&",
+        None => untyped,
+    }
+}
+
+/// The operator taking the expression as a position of the hooks, where an iterator stays as is
+pub fn position_op(expr: &Node, code: &str) -> &'static str {
+    match type_of(expr, code) {
+        Some(ty) if ty.class == IrClass::Iterator && !ty.is_pointer => "",
+        _ => address_op(expr, "", code),
+    }
+}
+
+/// The operator taking the expression as a reference, i.e., `*` for a pointer
+pub fn reference_op(expr: &Node, is_pointer: bool, code: &str) -> &'static str {
+    let is_address = expr.kind() == "pointer_expression"
+        && expr.child_by_field_name("operator").unwrap().kind() == "&";
+    let is_pointer = is_address || type_of(expr, code).map_or(is_pointer, |ty| ty.is_pointer);
+    if is_pointer {
+        "*"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::{Parser, Tree};
+
+    fn parse(code: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_cpp::language()).unwrap();
+        parser.parse(code, None).unwrap()
+    }
+
+    /// The types of the arguments of `use(..)` in the code
+    fn argument_types(code: &str) -> Vec<Option<ExprType>> {
+        let tree = parse(code);
+        let calls = get_children_of_kind(&tree.root_node(), "call_expression");
+        let call = calls
+            .iter()
+            .find(|call| {
+                call.child_by_field_name("function")
+                    .unwrap()
+                    .to_source(code)
+                    == "use"
+            })
+            .unwrap();
+        let arguments = call.child_by_field_name("arguments").unwrap();
+        let mut cursor = arguments.walk();
+        let types = arguments
+            .named_children(&mut cursor)
+            .map(|argument| type_of(&argument, code))
+            .collect();
+        types
+    }
+
+    #[test]
+    fn auto_pointer_from_cast() {
+        let code = "void f(Value *V) { auto *I = cast<Instruction>(V); auto *C = dyn_cast<CallInst>(V); use(I, C); }";
+        assert_eq!(
+            argument_types(code),
+            [
+                Some(ExprType::pointer(IrClass::Instruction)),
+                Some(ExprType::pointer(IrClass::Instruction))
+            ]
+        );
+    }
+
+    #[test]
+    fn typedef_and_alias() {
+        let code = "\
+typedef BasicBlock::iterator BBIt;
+typedef Instruction *InstPtr, InstObj;
+using IP = Instruction *;
+namespace {
+using It = BasicBlock::InstListType::reverse_iterator;
+void f(BBIt A, InstPtr B, InstObj &C, IP D, It E, IP *F) { use(A, B, C, D, E, F); }
+}";
+        assert_eq!(
+            argument_types(code),
+            [
+                Some(ExprType::object(IrClass::Iterator)),
+                Some(ExprType::pointer(IrClass::Instruction)),
+                Some(ExprType::object(IrClass::Instruction)),
+                Some(ExprType::pointer(IrClass::Instruction)),
+                Some(ExprType::object(IrClass::Iterator)),
+                Some(ExprType::object(IrClass::Other)),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_instruction_iterators() {
+        let code = "\
+void f(Function &F, BasicBlock &BB, Instruction *I, Function::iterator A,
+       std::vector<Instruction *>::iterator B, BasicBlock::const_iterator C) {
+  use(A, B, C, F.begin(), BB.begin(), I->getIterator(), BB.getIterator());
+}";
+        let iterator = Some(ExprType::object(IrClass::Iterator));
+        assert_eq!(
+            argument_types(code),
+            [
+                Some(ExprType::object(IrClass::Other)),
+                Some(ExprType::object(IrClass::Other)),
+                iterator,
+                None,
+                iterator,
+                iterator,
+                None,
+            ]
+        );
+    }
+}